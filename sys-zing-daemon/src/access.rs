@@ -0,0 +1,215 @@
+use crate::peer::{group_id, user_id};
+use crate::{Error, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use zing_protocol::{Client, Command};
+
+/// Something a client may be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Play melodies right away
+    Play,
+    /// Add melodies to the queue
+    Queue,
+    /// Look at the status, the queue and the events of the daemon
+    Status,
+    /// Stop, skip, pause and resume melodies of the same user
+    Control,
+    /// Stop, skip, pause and resume melodies of other users, and clear the queue
+    ControlOthers,
+}
+
+impl Permission {
+    const ALL: [Permission; 5] = [
+        Permission::Play,
+        Permission::Queue,
+        Permission::Status,
+        Permission::Control,
+        Permission::ControlOthers,
+    ];
+
+    /// The permissions needed to send `command`, none if anyone may send it.
+    ///
+    /// Commands that control playback need `ControlOthers` when they touch a melody of
+    /// another user, which `affects_others` tells. Playing a melody touches the current
+    /// melody when it ends or interrupts it, so it then needs `ControlOthers` as well.
+    #[must_use]
+    pub fn required(command: &Command, affects_others: bool) -> &'static [Permission] {
+        match command {
+            Command::Play(_) if affects_others => &[Permission::Play, Permission::ControlOthers],
+            Command::Play(_) => &[Permission::Play],
+            Command::Enqueue(_) => &[Permission::Queue],
+            Command::Status | Command::ListQueue | Command::Subscribe => &[Permission::Status],
+            Command::Stop
+            | Command::Skip
+            | Command::Pause
+            | Command::Resume
+            | Command::ClearQueue => {
+                if affects_others {
+                    &[Permission::ControlOthers]
+                } else {
+                    &[Permission::Control]
+                }
+            }
+            Command::Hello(_) => &[],
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Permission::Play => write!(f, "play"),
+            Permission::Queue => write!(f, "queue"),
+            Permission::Status => write!(f, "status"),
+            Permission::Control => write!(f, "control"),
+            Permission::ControlOthers => write!(f, "control_others"),
+        }
+    }
+}
+
+/// A user or group, by id or by name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Principal {
+    Id(u32),
+    Name(String),
+}
+
+/// The access policy as it is written in the configuration.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyFile {
+    allow_users: Vec<Principal>,
+    allow_groups: Vec<Principal>,
+    permissions: Option<HashSet<Permission>>,
+    group_permissions: HashMap<String, HashSet<Permission>>,
+}
+
+/// Decides who may connect to the daemon and which commands they may send.
+///
+/// The default policy lets everyone do everything. Root may always do everything.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PolicyFile")]
+pub struct Policy {
+    /// The users that may connect, on top of the allowed groups
+    users: HashSet<u32>,
+
+    /// The groups whose members may connect, everyone may connect if neither is restricted
+    groups: HashSet<u32>,
+
+    /// What everyone who may connect can do
+    permissions: HashSet<Permission>,
+
+    /// What members of a group can do on top of that
+    group_permissions: HashMap<u32, HashSet<Permission>>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            users: HashSet::new(),
+            groups: HashSet::new(),
+            permissions: Permission::ALL.into(),
+            group_permissions: HashMap::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// Parses a policy written in TOML.
+    ///
+    /// # Errors
+    /// Returns an error if the policy cannot be parsed or names an unknown user or group.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str::<PolicyFile>(text)
+            .map_err(Error::Toml)?
+            .try_into()
+    }
+
+    /// Whether the policy needs the groups of a client to decide, which are costly to look up.
+    #[must_use]
+    pub fn uses_groups(&self) -> bool {
+        !self.groups.is_empty() || !self.group_permissions.is_empty()
+    }
+
+    /// Whether a client that belongs to `groups` may connect at all.
+    #[must_use]
+    pub fn may_connect(&self, client: &Client, groups: &[u32]) -> bool {
+        client.uid == 0
+            || (self.users.is_empty() && self.groups.is_empty())
+            || self.users.contains(&client.uid)
+            || groups.iter().any(|group| self.groups.contains(group))
+    }
+
+    /// Whether a client that belongs to `groups` has `permission`.
+    #[must_use]
+    pub fn allows(&self, client: &Client, groups: &[u32], permission: Permission) -> bool {
+        client.uid == 0
+            || self.permissions.contains(&permission)
+            || groups.iter().any(|group| {
+                self.group_permissions
+                    .get(group)
+                    .is_some_and(|permissions| permissions.contains(&permission))
+            })
+    }
+}
+
+impl TryFrom<PolicyFile> for Policy {
+    type Error = Error;
+
+    /// Turns the names of users and groups into ids.
+    fn try_from(file: PolicyFile) -> Result<Self> {
+        let users = file
+            .allow_users
+            .into_iter()
+            .map(|user| match user {
+                Principal::Id(id) => Ok(id),
+                Principal::Name(name) => user_id(&name)?.ok_or(Error::UnknownUser(name)),
+            })
+            .collect::<Result<_>>()?;
+
+        let groups = file
+            .allow_groups
+            .into_iter()
+            .map(resolve_group)
+            .collect::<Result<_>>()?;
+
+        let group_permissions = file
+            .group_permissions
+            .into_iter()
+            .map(|(group, permissions)| {
+                let group = match group.parse() {
+                    Ok(id) => Principal::Id(id),
+                    Err(_) => Principal::Name(group),
+                };
+
+                Ok((resolve_group(group)?, permissions))
+            })
+            .collect::<Result<_>>()?;
+
+        // Controlling the melodies of others has to be granted explicitly
+        let permissions = file.permissions.unwrap_or_else(|| {
+            Permission::ALL
+                .into_iter()
+                .filter(|permission| *permission != Permission::ControlOthers)
+                .collect()
+        });
+
+        Ok(Policy {
+            users,
+            groups,
+            permissions,
+            group_permissions,
+        })
+    }
+}
+
+fn resolve_group(group: Principal) -> Result<u32> {
+    match group {
+        Principal::Id(id) => Ok(id),
+        Principal::Name(name) => group_id(&name)?.ok_or(Error::UnknownGroup(name)),
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{Error, Result};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+pub use beep::BeepBackend;
+pub use null::NullBackend;
+pub use recording::{RecordingBackend, ToneEvent, ToneLog};
+
+pub mod beep;
+pub mod null;
+pub mod recording;
+
+/// A backend shared between the player and its playback thread.
+pub type SharedBackend = Arc<Mutex<dyn ToneBackend>>;
+
+/// A device that can play a single tone at a time.
+pub trait ToneBackend: Send {
+    /// Starts playing a tone at the given frequency in Hz, replacing the previous tone.
+    ///
+    /// # Errors
+    /// Returns an error if the device cannot be controlled.
+    fn set_frequency(&mut self, frequency: u16) -> Result<()>;
+
+    /// Stops playing any tone.
+    ///
+    /// # Errors
+    /// Returns an error if the device cannot be controlled.
+    fn silence(&mut self) -> Result<()> {
+        self.set_frequency(0)
+    }
+
+    /// Describes what the device is able to play.
+    fn capabilities(&self) -> Capabilities;
+}
+
+/// The limits of a `ToneBackend`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The lowest frequency in Hz the device can play
+    pub min_frequency: u16,
+
+    /// The highest frequency in Hz the device can play
+    pub max_frequency: u16,
+}
+
+impl Capabilities {
+    /// Moves a frequency into the range the device can play, leaving silence (0) alone.
+    #[must_use]
+    pub fn clamp(&self, frequency: u16) -> u16 {
+        if frequency == 0 {
+            0
+        } else {
+            frequency.clamp(self.min_frequency, self.max_frequency)
+        }
+    }
+}
+
+/// The backends that can be selected when starting the daemon.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// The PC speaker, driven through the `beep` crate
+    #[default]
+    Beep,
+
+    /// Plays nothing, useful when there is no speaker
+    Null,
+
+    /// Writes a timestamped log of every tone to a file
+    Record,
+}
+
+impl BackendKind {
+    /// Creates the backend this kind describes.
+    ///
+    /// The record file is only used by the recording backend, which requires one.
+    ///
+    /// # Errors
+    /// Returns an error if the recording backend has no file or it cannot be created.
+    pub fn create(self, record_file: Option<&Path>) -> Result<SharedBackend> {
+        Ok(match self {
+            BackendKind::Beep => Arc::new(Mutex::new(BeepBackend)),
+            BackendKind::Null => Arc::new(Mutex::new(NullBackend)),
+            BackendKind::Record => {
+                let path = record_file.ok_or(Error::NoRecordFile)?;
+                Arc::new(Mutex::new(RecordingBackend::to_file(path)?))
+            }
+        })
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BackendKind::Beep => write!(f, "beep"),
+            BackendKind::Null => write!(f, "null"),
+            BackendKind::Record => write!(f, "record"),
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "beep" => Ok(BackendKind::Beep),
+            "null" => Ok(BackendKind::Null),
+            "record" => Ok(BackendKind::Record),
+            _ => Err(format!(
+                "unknown backend '{s}', expected beep, null or record"
+            )),
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use super::{Capabilities, ToneBackend};
+use crate::{Error, Result};
+
+/// Plays tones on the PC speaker through the `beep` crate.
+///
+/// This requires access to the console device, which usually means running as root.
+#[derive(Debug, Default)]
+pub struct BeepBackend;
+
+impl ToneBackend for BeepBackend {
+    fn set_frequency(&mut self, frequency: u16) -> Result<()> {
+        ::beep::beep(frequency).map_err(Error::Beep)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // The speaker divides a 1.193182 MHz clock by a 16-bit counter
+        Capabilities {
+            min_frequency: 19,
+            max_frequency: 20_000,
+        }
+    }
+}
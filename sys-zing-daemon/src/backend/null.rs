@@ -0,0 +1,19 @@
+use super::{Capabilities, ToneBackend};
+use crate::Result;
+
+/// Accepts every tone and plays none of them.
+#[derive(Debug, Default)]
+pub struct NullBackend;
+
+impl ToneBackend for NullBackend {
+    fn set_frequency(&mut self, _frequency: u16) -> Result<()> {
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            min_frequency: 1,
+            max_frequency: u16::MAX,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use super::{Capabilities, ToneBackend};
+use crate::{Error, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A frequency change, timed from the moment the backend was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToneEvent {
+    pub at: Duration,
+    pub frequency: u16,
+}
+
+/// A handle to the tones recorded by a `RecordingBackend`.
+///
+/// The handle stays usable after the backend has been handed to a player.
+#[derive(Debug, Clone, Default)]
+pub struct ToneLog {
+    events: Arc<Mutex<Vec<ToneEvent>>>,
+}
+
+impl ToneLog {
+    /// Returns a copy of every frequency change recorded so far.
+    #[must_use]
+    pub fn events(&self) -> Vec<ToneEvent> {
+        self.events
+            .lock()
+            .map(|events| events.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Records every frequency change instead of playing it.
+///
+/// The tones are kept in memory and, if a file was given, written to it as lines
+/// of the form `<seconds> <frequency>`.
+#[derive(Debug)]
+pub struct RecordingBackend {
+    start: Instant,
+    log: ToneLog,
+    file: Option<BufWriter<File>>,
+}
+
+impl Default for RecordingBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordingBackend {
+    /// Creates a backend that records its tones in memory.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            log: ToneLog::default(),
+            file: None,
+        }
+    }
+
+    /// Creates a backend that records its tones in memory and to a file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created.
+    pub fn to_file(path: &Path) -> Result<Self> {
+        let file = File::create(path).map_err(Error::Io)?;
+
+        Ok(Self {
+            file: Some(BufWriter::new(file)),
+            ..Self::new()
+        })
+    }
+
+    /// A handle to the recorded tones.
+    #[must_use]
+    pub fn log(&self) -> ToneLog {
+        self.log.clone()
+    }
+}
+
+impl ToneBackend for RecordingBackend {
+    fn set_frequency(&mut self, frequency: u16) -> Result<()> {
+        let event = ToneEvent {
+            at: self.start.elapsed(),
+            frequency,
+        };
+
+        if let Some(file) = &mut self.file {
+            writeln!(file, "{:.6} {}", event.at.as_secs_f64(), event.frequency)
+                .and_then(|()| file.flush())
+                .map_err(Error::Io)?;
+        }
+
+        self.log
+            .events
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .push(event);
+
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            min_frequency: 1,
+            max_frequency: u16::MAX,
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::access::Policy;
+use crate::backend::BackendKind;
+use crate::peer::group_id;
+use crate::quiet::QuietHours;
+use crate::server::Limits;
+use crate::{BusyPolicy, Error, MelodyLimits, Result};
+use serde::Deserialize;
+use serde::de::{self, Deserializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The file the daemon reads its configuration from, unless told otherwise.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/zing/syszingd.toml";
+
+/// Everything that can be configured about the daemon, every section is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub socket: SocketConfig,
+    pub backend: BackendConfig,
+    pub playback: PlaybackConfig,
+    pub limits: MelodyLimits,
+    pub connections: Limits,
+    pub quiet_hours: Option<QuietHours>,
+    pub access: Policy,
+}
+
+/// Where the daemon listens and who may open the socket.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// The socket to listen on, `ZING_SOCKET` or /run/zingd.sock by default
+    pub path: PathBuf,
+
+    /// The permissions of the socket file
+    pub mode: u32,
+
+    /// The group that owns the socket file, by name or id
+    #[serde(deserialize_with = "group")]
+    pub group: Option<u32>,
+
+    /// How long the daemon waits without connections or melodies before it exits
+    #[serde(deserialize_with = "optional_duration")]
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            path: zing_protocol::socket_path(),
+            mode: 0o666,
+            group: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+/// The device the daemon plays its tones on.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    #[serde(deserialize_with = "from_str")]
+    pub kind: BackendKind,
+
+    /// The file the record backend writes its tones to
+    pub record_file: Option<PathBuf>,
+}
+
+/// How melodies are played.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlaybackConfig {
+    #[serde(deserialize_with = "from_str")]
+    pub busy_policy: BusyPolicy,
+}
+
+impl Config {
+    /// Reads the configuration from a TOML file.
+    ///
+    /// The configuration still has to be validated once it is complete.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read or does not describe a configuration.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::from_toml(&fs::read_to_string(path).map_err(Error::Io)?)
+    }
+
+    /// Parses a configuration written in TOML.
+    ///
+    /// # Errors
+    /// Returns an error if the text does not describe a configuration, for example
+    /// because of unknown settings or users and groups that do not exist.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        toml::from_str(text).map_err(Error::Toml)
+    }
+
+    /// Checks the settings that depend on each other or cannot be checked while parsing.
+    ///
+    /// # Errors
+    /// Returns an error describing the first setting that is invalid.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+
+        if self.socket.mode > 0o777 {
+            return invalid("socket.mode must be a permission mode like 0o660");
+        }
+
+        if self.backend.kind == BackendKind::Record && self.backend.record_file.is_none() {
+            return invalid("backend.record_file is required by the record backend");
+        }
+
+        if self
+            .socket
+            .idle_timeout
+            .is_some_and(|timeout| timeout.is_zero())
+        {
+            return invalid("socket.idle_timeout must be longer than zero");
+        }
+
+        if self.connections.timeout.is_zero() {
+            return invalid("connections.timeout must be longer than zero");
+        }
+
+        if self.connections.max_message_size == 0 {
+            return invalid("connections.max_message_size must be at least one byte");
+        }
+
+        if self.connections.max_connections_per_user == 0 {
+            return invalid("connections.max_connections_per_user must be at least one");
+        }
+
+        if self
+            .quiet_hours
+            .is_some_and(|quiet_hours| quiet_hours.start == quiet_hours.end)
+        {
+            return invalid("quiet_hours.start and quiet_hours.end must differ");
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes a value from a string with its `FromStr` implementation.
+pub(crate) fn from_str<'de, D, T>(deserializer: D) -> core::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Deserializes a duration written for humans, like "30s" or "1m 30s".
+pub(crate) fn duration<'de, D>(deserializer: D) -> core::result::Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    from_str::<D, humantime::Duration>(deserializer).map(Into::into)
+}
+
+/// Deserializes an optional duration written for humans.
+pub(crate) fn optional_duration<'de, D>(
+    deserializer: D,
+) -> core::result::Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    duration(deserializer).map(Some)
+}
+
+/// Deserializes a group by name or id into its id.
+fn group<'de, D>(deserializer: D) -> core::result::Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Group {
+        Id(u32),
+        Name(String),
+    }
+
+    match Group::deserialize(deserializer)? {
+        Group::Id(id) => Ok(Some(id)),
+        Group::Name(name) => match group_id(&name).map_err(de::Error::custom)? {
+            Some(id) => Ok(Some(id)),
+            None => Err(de::Error::custom(Error::UnknownGroup(name))),
+        },
+    }
+}
@@ -1,4 +1,6 @@
 use derive_more::{Display, Error};
+use std::time::Duration;
+use zing_protocol::{ErrorReason, Priority};
 
 pub type Result<T> = core::result::Result<T, Error>;
 
@@ -11,4 +13,40 @@ pub enum Error {
     LockPoisoned,
     CouldNotJoinThread,
     NoChordsProvided,
+    HigherPriorityPlaying,
+    NoRecordFile,
+    Toml(toml::de::Error),
+    #[display("Unknown user '{_0}'")]
+    UnknownUser(#[error(not(source))] String),
+    #[display("Unknown group '{_0}'")]
+    UnknownGroup(#[error(not(source))] String),
+    PermissionDenied,
+    #[display("Invalid configuration: {_0}")]
+    InvalidConfig(#[error(not(source))] String),
+    #[display("A chord is longer than {_0:?}")]
+    ChordTooLong(#[error(not(source))] Duration),
+    #[display("The melody is longer than {_0:?}")]
+    MelodyTooLong(#[error(not(source))] Duration),
+    #[display("Only melodies with {_0} priority may play during quiet hours")]
+    QuietHours(#[error(not(source))] Priority),
+    #[display("systemd passed more than one socket")]
+    TooManySockets,
+}
+
+impl Error {
+    /// The reason reported back to the client that sent the failing command.
+    #[must_use]
+    pub fn reason(&self) -> ErrorReason {
+        match self {
+            Error::NoChordsProvided => ErrorReason::NoChordsProvided,
+            Error::HigherPriorityPlaying => ErrorReason::Busy,
+            Error::PermissionDenied => ErrorReason::PermissionDenied,
+            Error::ChordTooLong(max) => ErrorReason::ChordTooLong(*max),
+            Error::MelodyTooLong(max) => ErrorReason::MelodyTooLong(*max),
+            Error::QuietHours(min) => ErrorReason::QuietHours(*min),
+            Error::Beep(e) => ErrorReason::Backend(e.to_string()),
+            Error::ZingProtocol(e) => ErrorReason::InvalidCommand(e.to_string()),
+            e => ErrorReason::Internal(e.to_string()),
+        }
+    }
 }
@@ -0,0 +1,47 @@
+use crate::melody::Melody;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use zing_protocol::Event;
+
+/// How many events a subscriber may fall behind before it is dropped.
+const BACKLOG: usize = 256;
+
+/// Hands every event to all subscribers.
+///
+/// Subscribers that went away or cannot keep up are forgotten, so a slow subscriber
+/// never holds up playback.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<SyncSender<Event>>>>,
+}
+
+impl EventBus {
+    /// Returns a receiver for all events emitted from now on.
+    #[must_use]
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(BACKLOG);
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+
+        receiver
+    }
+
+    /// Sends `event` to every subscriber.
+    pub fn emit(&self, event: &Event) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| subscriber.try_send(event.clone()).is_ok());
+        }
+    }
+
+    /// Announces that `melody` started playing.
+    pub fn started(&self, melody: &Melody) {
+        self.emit(&Event::MelodyStarted {
+            client: melody.client(),
+            priority: melody.priority(),
+            chords: melody.chord_count(),
+            duration: melody.duration(),
+        });
+    }
+}
@@ -1,115 +1,607 @@
+use crate::backend::{SharedBackend, ToneBackend};
+use crate::events::EventBus;
 use crate::melody::Melody;
-use beep::beep;
+use crate::quiet::{QuietHours, TimeOfDay};
 use log::{error, info, trace, warn};
-use std::sync::{Arc, RwLock};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
-use zing_protocol::Chord;
-use zing_protocol::Command;
-use zing_protocol::Command::{Pause, Play, Resume, Stop};
+use std::time::{Duration, Instant};
+use zing_protocol::Command::{
+    ClearQueue, Enqueue, Hello, ListQueue, Pause, Play, Resume, Skip, Status, Stop, Subscribe,
+};
+use zing_protocol::{
+    Capabilities, Chord, Client, Command, Event, PlaybackState, QueueEntry, Response,
+};
 
 pub use error::{Error, Result};
 
+pub mod access;
+pub mod backend;
+pub mod config;
 pub mod error;
+pub mod events;
 pub mod melody;
+pub mod peer;
+pub mod quiet;
+pub mod server;
+pub mod systemd;
+
+/// What happens to a melody that arrives while a melody with a higher priority plays.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BusyPolicy {
+    /// The melody waits in the queue until the more important melodies are done.
+    #[default]
+    Wait,
+    /// The melody is refused.
+    Drop,
+}
+
+impl FromStr for BusyPolicy {
+    type Err = String;
 
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wait" => Ok(BusyPolicy::Wait),
+            "drop" => Ok(BusyPolicy::Drop),
+            _ => Err(format!("unknown busy policy '{s}', expected wait or drop")),
+        }
+    }
+}
+
+impl fmt::Display for BusyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusyPolicy::Wait => write!(f, "wait"),
+            BusyPolicy::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+/// Limits on the melodies the player accepts, nothing is limited by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MelodyLimits {
+    /// The longest a single chord may take, including its gap
+    #[serde(deserialize_with = "config::optional_duration")]
+    pub max_chord_duration: Option<Duration>,
+
+    /// The longest a whole melody may take
+    #[serde(deserialize_with = "config::optional_duration")]
+    pub max_melody_duration: Option<Duration>,
+}
+
+/// The melodies shared between the player and its playback thread.
 #[derive(Default)]
+struct Playback {
+    current: Option<Melody>,
+
+    /// Waiting melodies, ordered from the highest to the lowest priority
+    queue: VecDeque<Melody>,
+
+    /// Melodies interrupted by a more important one, the last one is resumed first
+    interrupted: Vec<Melody>,
+
+    /// Set while a playback thread is alive
+    running: bool,
+
+    /// Asks the playback thread to return without touching the melodies
+    halted: bool,
+}
+
+/// The playback and the means to wake the playback thread when it changes.
+#[derive(Default)]
+struct SharedPlayback {
+    playback: Mutex<Playback>,
+
+    /// Notified whenever the playback is locked to be changed
+    changed: Condvar,
+}
+
+impl SharedPlayback {
+    fn lock(&self) -> Result<MutexGuard<'_, Playback>> {
+        self.playback.lock().map_err(|_| Error::LockPoisoned)
+    }
+}
+
+impl Playback {
+    /// Whether the playback thread should stop what it is doing right now.
+    fn interrupted(&self) -> bool {
+        self.halted || !self.current.as_ref().is_some_and(Melody::is_playing)
+    }
+
+    /// The melody that is playing or paused, if it is not done yet.
+    fn active(&self) -> Option<&Melody> {
+        self.current
+            .as_ref()
+            .filter(|melody| !melody.was_stopped() && !melody.is_finished())
+    }
+
+    fn state(&self) -> PlaybackState {
+        let Some(melody) = self.active() else {
+            // A running playback thread moves on to the next melody right away
+            let pending = !self.queue.is_empty() || !self.interrupted.is_empty();
+
+            return if self.running && pending {
+                PlaybackState::Playing
+            } else {
+                PlaybackState::Idle
+            };
+        };
+
+        if melody.is_playing() {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::Paused
+        }
+    }
+
+    /// Queues a melody behind all melodies with the same or a higher priority.
+    fn enqueue(&mut self, melody: Melody) {
+        let index = self
+            .queue
+            .iter()
+            .position(|queued| queued.priority() < melody.priority())
+            .unwrap_or(self.queue.len());
+
+        self.queue.insert(index, melody);
+    }
+
+    /// Takes the melody that should play next.
+    ///
+    /// Interrupted melodies resume before queued melodies of the same priority.
+    fn next(&mut self) -> Option<Melody> {
+        let resume = match (self.interrupted.last(), self.queue.front()) {
+            (Some(interrupted), Some(queued)) => interrupted.priority() >= queued.priority(),
+            (interrupted, _) => interrupted.is_some(),
+        };
+
+        if resume {
+            self.interrupted.pop()
+        } else {
+            self.queue.pop_front()
+        }
+    }
+}
+
 pub struct MelodyPlayer {
     play_handle: Option<JoinHandle<()>>,
-    melody: Option<Arc<RwLock<Melody>>>,
+    playback: Arc<SharedPlayback>,
+    backend: SharedBackend,
+    busy_policy: BusyPolicy,
+    limits: MelodyLimits,
+    quiet_hours: Option<QuietHours>,
+    events: EventBus,
 }
 
 impl MelodyPlayer {
+    /// Creates a player that plays its melodies on `backend`.
+    #[must_use]
+    pub fn new(backend: SharedBackend) -> Self {
+        Self {
+            play_handle: None,
+            playback: Arc::default(),
+            backend,
+            busy_policy: BusyPolicy::default(),
+            limits: MelodyLimits::default(),
+            quiet_hours: None,
+            events: EventBus::default(),
+        }
+    }
+
+    /// Handles less important melodies according to `busy_policy`.
     #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn with_busy_policy(mut self, busy_policy: BusyPolicy) -> Self {
+        self.busy_policy = busy_policy;
+        self
     }
 
-    pub fn handle_command(&mut self, command: Command) {
-        trace!("Processing command");
+    /// Refuses melodies that do not fit within `limits`.
+    #[must_use]
+    pub fn with_limits(mut self, limits: MelodyLimits) -> Self {
+        self.limits = limits;
+        self
+    }
 
-        match command {
-            Play(data) => {
-                if let Ok(melody) = Melody::from_data(data) {
-                    self.play(melody).unwrap_or_else(|e| error!("{e}"));
-                } else {
-                    error!("Failed to create melody");
-                }
+    /// Only lets important melodies play during `quiet_hours`.
+    #[must_use]
+    pub fn with_quiet_hours(mut self, quiet_hours: Option<QuietHours>) -> Self {
+        self.quiet_hours = quiet_hours;
+        self
+    }
+
+    /// Replaces the settings given to the builders, melodies that were already accepted stay.
+    pub fn reconfigure(
+        &mut self,
+        busy_policy: BusyPolicy,
+        limits: MelodyLimits,
+        quiet_hours: Option<QuietHours>,
+    ) {
+        self.busy_policy = busy_policy;
+        self.limits = limits;
+        self.quiet_hours = quiet_hours;
+    }
+
+    /// Executes a command and returns the response for the client that sent it.
+    ///
+    /// Successful commands are answered with the playback state after the command,
+    /// failures are logged and answered with the reason they failed.
+    pub fn handle_command(&mut self, command: Command, client: Client) -> Response {
+        trace!("Processing command from {client}");
+
+        let result = match command {
+            Play(data) => Melody::from_data(data, client)
+                .and_then(|melody| self.admit(melody))
+                .and_then(|melody| self.play(melody))
+                .and_then(|()| self.state_response()),
+            Enqueue(data) => Melody::from_data(data, client)
+                .and_then(|melody| self.admit(melody))
+                .and_then(|melody| self.enqueue(melody))
+                .and_then(|()| self.state_response()),
+            Stop => self.stop().and_then(|()| self.state_response()),
+            Skip => self.skip().and_then(|()| self.state_response()),
+            Pause => self.pause().and_then(|()| self.state_response()),
+            Resume => self.resume().and_then(|()| self.state_response()),
+            ClearQueue => self.clear_queue().and_then(|()| self.state_response()),
+            ListQueue => self.queue().map(Response::Queue),
+            Status => self.status().map(Response::Status),
+            Hello(hello) => {
+                info!(
+                    "{client} connected with {}, protocol versions {} to {}",
+                    hello.agent, hello.min_version, hello.max_version
+                );
+                self.capabilities().map(Response::Hello)
             }
-            Stop => self.stop().unwrap_or_else(|e| error!("{e}")),
-            Pause => self.pause().unwrap_or_else(|e| error!("{e}")),
-            Resume => self.resume().unwrap_or_else(|e| error!("{e}")),
+
+            // The server streams the events of `subscribe` after this answer
+            Subscribe => Ok(Response::Ok),
+        };
+
+        result.unwrap_or_else(|e| {
+            error!("{e}");
+            Response::Error(e.reason())
+        })
+    }
+
+    /// Refuses melodies that exceed the limits or are not important enough for quiet hours.
+    fn admit(&self, melody: Melody) -> Result<Melody> {
+        if let Some(max) = self.limits.max_chord_duration
+            && melody.longest_chord() > max
+        {
+            return Err(Error::ChordTooLong(max));
+        }
+
+        if let Some(max) = self.limits.max_melody_duration
+            && melody.duration() > max
+        {
+            return Err(Error::MelodyTooLong(max));
+        }
+
+        if let Some(quiet_hours) = self.quiet_hours
+            && !quiet_hours.allows(melody.priority(), TimeOfDay::now())
+        {
+            info!("Refused melody during quiet hours");
+            return Err(Error::QuietHours(quiet_hours.min_priority));
+        }
+
+        Ok(melody)
+    }
+
+    fn state_response(&self) -> Result<Response> {
+        self.state().map(Response::State)
+    }
+
+    /// Returns whether a melody is currently playing, paused or absent.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
+    pub fn state(&self) -> Result<PlaybackState> {
+        Ok(self.read_playback()?.state())
+    }
+
+    /// Returns a snapshot of the melody that is currently playing.
+    ///
+    /// An idle player reports an empty status without a position or client.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
+    pub fn status(&self) -> Result<zing_protocol::Status> {
+        let playback = self.read_playback()?;
+        let state = playback.state();
+
+        let Some(melody) = playback.active() else {
+            return Ok(zing_protocol::Status {
+                state,
+                ..zing_protocol::Status::default()
+            });
         };
+
+        Ok(zing_protocol::Status {
+            state,
+            position: melody.position(),
+            total: melody.chord_count(),
+            elapsed: melody.elapsed(),
+            remaining: melody.remaining(),
+            priority: melody.priority(),
+            client: Some(melody.client()),
+        })
     }
 
+    /// Returns the clients whose melodies `command` would stop, skip, pause, remove, or
+    /// end or interrupt by playing another melody.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
+    pub fn affected_clients(&self, command: &Command) -> Result<Vec<Client>> {
+        let playback = self.read_playback()?;
+        let current = playback.active().map(Melody::client);
+        let queued = playback.queue.iter().map(Melody::client);
+
+        Ok(match command {
+            Stop => current
+                .into_iter()
+                .chain(queued)
+                .chain(playback.interrupted.iter().map(Melody::client))
+                .collect(),
+            Skip | Pause | Resume => current.into_iter().collect(),
+
+            // A melody that is at least as important replaces or interrupts the current one
+            Play(data) => playback
+                .active()
+                .filter(|melody| melody.priority() <= data.priority)
+                .map(Melody::client)
+                .into_iter()
+                .collect(),
+            ClearQueue => queued.collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    /// Returns a receiver for everything that happens to the melodies from now on.
+    #[must_use]
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Describes the daemon and the buzzer it plays on.
+    ///
+    /// # Errors
+    /// Returns an error if the backend lock is poisoned.
+    pub fn capabilities(&self) -> Result<Capabilities> {
+        let backend = self
+            .backend
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .capabilities();
+
+        Ok(Capabilities {
+            agent: format!("syszingd {}", env!("CARGO_PKG_VERSION")),
+            min_version: zing_protocol::MIN_PROTOCOL_VERSION,
+            max_version: zing_protocol::PROTOCOL_VERSION,
+            min_frequency: backend.min_frequency,
+            max_frequency: backend.max_frequency,
+        })
+    }
+
+    /// Returns the melodies waiting in the queue, next one first.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
+    pub fn queue(&self) -> Result<Vec<QueueEntry>> {
+        let playback = self.read_playback()?;
+
+        Ok(playback
+            .queue
+            .iter()
+            .map(|melody| QueueEntry {
+                chords: melody.chord_count(),
+                duration: melody.duration(),
+                priority: melody.priority(),
+                client: melody.client(),
+            })
+            .collect())
+    }
+
+    /// Plays a melody right away, keeping the queue.
+    ///
+    /// A melody with the same priority as the current melody replaces it, a melody with a
+    /// higher priority interrupts it until it is done. A melody with a lower priority
+    /// is queued or dropped depending on the busy policy.
+    ///
+    /// # Errors
+    /// Returns an error if the buzzer cannot be controlled, the playback lock is poisoned,
+    /// the previous playback thread cannot be joined or the melody was dropped.
     pub fn play(&mut self, mut melody: Melody) -> Result<()> {
         trace!("Playing melody");
 
+        // A more important melody keeps playing without being cut short
+        let current_priority = self.read_playback()?.active().map(Melody::priority);
+        if current_priority.is_some_and(|priority| priority > melody.priority()) {
+            return self.wait_or_drop(melody);
+        }
+
+        // Make sure the buzzer can be controlled before reporting success, the previous
+        // melody is stopped anyway
+        self.silence()?;
+
         // Stop any previous songs from playing
-        self.stop()?;
+        self.halt()?;
+
+        {
+            let mut playback = self.write_playback()?;
+
+            if let Some(mut previous) = playback.current.take() {
+                if current_priority.is_some_and(|priority| priority < melody.priority()) {
+                    previous.pause();
+                    playback.interrupted.push(previous);
+                    self.events.emit(&Event::Preempted {
+                        priority: melody.priority(),
+                    });
+                    info!("Interrupted melody");
+                } else {
+                    self.end(previous);
+                }
+            }
 
-        // Make sure the melody can play in the first instance
-        melody.resume();
+            // Make sure the melody can play in the first instance
+            melody.resume();
+            self.events.started(&melody);
+            playback.current = Some(melody);
+        }
 
-        let melody_ref = Arc::new(RwLock::new(melody));
-        self.melody = Some(melody_ref.clone());
-        self.play_handle = Some(thread::spawn(move || Self::play_melody(&melody_ref)));
+        self.start()?;
         info!("Started melody");
 
         Ok(())
     }
 
+    /// Adds a melody to the queue, it starts right away if nothing else is playing.
+    ///
+    /// # Errors
+    /// Returns an error if nothing is playing and the buzzer cannot be controlled, the
+    /// playback lock is poisoned or a finished playback thread cannot be joined.
+    pub fn enqueue(&mut self, melody: Melody) -> Result<()> {
+        trace!("Queueing melody");
+
+        let current_priority = self.read_playback()?.active().map(Melody::priority);
+        if current_priority.is_some_and(|priority| priority > melody.priority()) {
+            return self.wait_or_drop(melody);
+        }
+
+        // Make sure the buzzer can be controlled before reporting success. Silencing it
+        // would cut a playing note short, the playback thread reports its errors instead.
+        if current_priority.is_none() {
+            self.silence()?;
+        }
+
+        self.write_playback()?.enqueue(melody);
+        self.start()?;
+        info!("Queued melody");
+
+        Ok(())
+    }
+
+    /// Applies the busy policy to a melody that is less important than the current one.
+    fn wait_or_drop(&mut self, melody: Melody) -> Result<()> {
+        match self.busy_policy {
+            BusyPolicy::Wait => {
+                self.write_playback()?.enqueue(melody);
+                info!("Queued melody behind a more important one");
+                Ok(())
+            }
+            BusyPolicy::Drop => {
+                info!("Dropped melody, a more important one is playing");
+                Err(Error::HigherPriorityPlaying)
+            }
+        }
+    }
+
+    /// Stops the current melody, clears the queue and forgets interrupted melodies.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned or the playback thread cannot be joined.
     pub fn stop(&mut self) -> Result<()> {
         trace!("Stopping melody");
 
-        if let Some(melody) = &self.melody {
-            let Ok(mut melody) = melody.write() else {
-                return Err(Error::LockPoisoned);
-            };
+        self.halt()?;
+
+        let mut playback = self.write_playback()?;
+        playback.queue.clear();
+        playback.interrupted.clear();
 
-            melody.stop();
+        if let Some(melody) = playback.current.take() {
+            self.end(melody);
             info!("Melody stopped");
         } else {
             info!("No melody to stop");
         }
 
-        trace!("Joining thread");
-        if let Some(play_handle) = self.play_handle.take() {
-            play_handle.join().map_err(|_| Error::CouldNotJoinThread)?;
-            info!("Thread joined");
+        Ok(())
+    }
+
+    /// Stops all melodies and makes sure the speaker is silent, for when the daemon exits.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned, the playback thread cannot be joined
+    /// or the speaker cannot be silenced.
+    pub fn shutdown(&mut self) -> Result<()> {
+        trace!("Shutting down");
+
+        let stopped = self.stop();
+
+        // The playback thread may have started another tone right before it was joined
+        self.silence()?;
+        info!("Speaker silenced");
+
+        stopped
+    }
+
+    /// Stops the current melody and continues with an interrupted or queued melody.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned or the playback thread cannot be joined.
+    pub fn skip(&mut self) -> Result<()> {
+        trace!("Skipping melody");
+
+        self.halt()?;
+
+        let current = self.write_playback()?.current.take();
+        if let Some(melody) = current {
+            self.end(melody);
+            info!("Melody skipped");
         } else {
-            info!("No thread to join");
+            info!("No melody to skip");
         }
 
+        self.start()
+    }
+
+    /// Removes all melodies from the queue without touching the current melody.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
+    pub fn clear_queue(&mut self) -> Result<()> {
+        trace!("Clearing queue");
+
+        self.write_playback()?.queue.clear();
+        info!("Queue cleared");
+
         Ok(())
     }
 
+    /// Pauses the current melody.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
     pub fn pause(&mut self) -> Result<()> {
         trace!("Pausing melody");
 
-        if let Some(melody) = &self.melody {
-            let Ok(mut melody) = melody.write() else {
-                return Err(Error::LockPoisoned);
-            };
-
+        if let Some(melody) = &mut self.write_playback()?.current {
             melody.pause();
+            self.events.emit(&Event::Paused);
             info!("Melody paused");
         } else {
             info!("No melody to pause");
         }
 
+        let _ = self.silence();
         Ok(())
     }
 
+    /// Resumes the current melody.
+    ///
+    /// # Errors
+    /// Returns an error if the playback lock is poisoned.
     pub fn resume(&mut self) -> Result<()> {
         trace!("Resuming melody");
 
-        if let Some(melody) = &self.melody {
-            let Ok(mut melody) = melody.write() else {
-                return Err(Error::LockPoisoned);
-            };
-
+        if let Some(melody) = &mut self.write_playback()?.current {
             melody.resume();
+            self.events.emit(&Event::Resumed);
             info!("Melody resumed");
         } else {
             info!("No melody to resume");
@@ -118,74 +610,248 @@ impl MelodyPlayer {
         Ok(())
     }
 
-    fn play_melody(melody: &Arc<RwLock<Melody>>) {
-        loop {
-            let chord;
-            let chord_duration;
-
-            // Melody reader has its own scope
-            {
-                let melody = match melody.read() {
-                    Ok(melody) => melody,
-                    Err(e) => {
-                        error!("Could not read melody: {e}");
-                        return;
-                    }
-                };
+    /// Stops a melody that was taken from the playback, telling subscribers how it ended.
+    fn end(&self, mut melody: Melody) {
+        if melody.was_stopped() {
+            return;
+        }
 
-                if melody.was_stopped() {
-                    info!("Melody stopped");
-                    return;
-                }
+        // The playback thread may have been halted right after the last chord
+        if melody.is_finished() {
+            self.events.emit(&Event::Finished);
+        } else {
+            self.events.emit(&Event::Stopped);
+        }
+
+        melody.stop();
+    }
+
+    /// Spawns a playback thread, unless one is already running.
+    fn start(&mut self) -> Result<()> {
+        {
+            let mut playback = self.write_playback()?;
+            if playback.running {
+                return Ok(());
+            }
+
+            playback.running = true;
+        }
+
+        // A thread that is no longer running has finished or is about to
+        self.join()?;
+
+        let playback = self.playback.clone();
+        let backend = self.backend.clone();
+        let events = self.events.clone();
+        self.play_handle = Some(thread::spawn(move || {
+            if let Err(e) = Self::play_melodies(&playback, &backend, &events) {
+                error!("Playback failed: {e}");
+            }
+        }));
+        Ok(())
+    }
 
-                if melody.is_finished() {
+    /// Makes the playback thread return, leaving the melodies as they are.
+    fn halt(&mut self) -> Result<()> {
+        self.write_playback()?.halted = true;
+        let _ = self.silence();
+
+        let joined = self.join();
+        self.write_playback()?.halted = false;
+        joined
+    }
+
+    fn join(&mut self) -> Result<()> {
+        trace!("Joining thread");
+        if let Some(play_handle) = self.play_handle.take() {
+            play_handle.join().map_err(|_| Error::CouldNotJoinThread)?;
+            info!("Thread joined");
+        } else {
+            info!("No thread to join");
+        }
+
+        Ok(())
+    }
+
+    fn silence(&self) -> Result<()> {
+        self.backend
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .silence()
+    }
+
+    fn read_playback(&self) -> Result<MutexGuard<'_, Playback>> {
+        self.playback.lock()
+    }
+
+    /// Locks the playback to change it, waking the playback thread.
+    ///
+    /// The playback thread cannot return from its wait before the guard is dropped, so it
+    /// always sees the change.
+    fn write_playback(&self) -> Result<MutexGuard<'_, Playback>> {
+        let playback = self.read_playback()?;
+        self.playback.changed.notify_all();
+        Ok(playback)
+    }
+
+    /// Plays melodies until the queue is done or the thread is halted.
+    ///
+    /// The thread sleeps on the condition variable while the melody is paused, and is woken
+    /// by every change to the playback.
+    fn play_melodies(
+        shared: &SharedPlayback,
+        backend: &SharedBackend,
+        events: &EventBus,
+    ) -> Result<()> {
+        let mut playback = shared.lock()?;
+
+        loop {
+            if playback.halted {
+                playback.running = false;
+                return Ok(());
+            }
+
+            let done = playback
+                .current
+                .as_ref()
+                .is_none_or(|melody| melody.was_stopped() || melody.is_finished());
+
+            // Continue with an interrupted or queued melody once the current one is done
+            if done {
+                if playback
+                    .current
+                    .take()
+                    .is_some_and(|melody| !melody.was_stopped())
+                {
+                    events.emit(&Event::Finished);
                     info!("Melody finished");
-                    return;
                 }
 
-                if !melody.is_playing() && !melody.was_stopped() {
-                    continue;
-                }
+                let Some(mut melody) = playback.next() else {
+                    info!("Queue finished");
+                    playback.running = false;
+                    return Ok(());
+                };
 
-                chord = melody.get_chord();
-                chord_duration = melody.get_chord_duration();
+                melody.resume();
+                events.started(&melody);
+                playback.current = Some(melody);
+                info!("Started next melody");
             }
 
-            match Self::play_chord(&chord, chord_duration) {
-                Ok(()) => (),
-                Err(e) => warn!("Could not play chord: {e}"),
+            let Some(melody) = &playback.current else {
+                continue;
+            };
+
+            // Sleep until the melody is resumed, stopped or replaced
+            if !melody.is_playing() {
+                playback = shared
+                    .changed
+                    .wait(playback)
+                    .map_err(|_| Error::LockPoisoned)?;
+                continue;
             }
 
-            // After playing a note, grab a write lock and increment its position counter
-            let mut melody = match melody.write() {
-                Ok(melody) => melody,
+            let chord = melody.get_chord();
+            let offset = melody.chord_offset();
+            if offset.is_zero() {
+                events.emit(&Event::ChordAdvanced {
+                    position: melody.position(),
+                    chord: chord.clone(),
+                });
+            }
+            drop(playback);
+
+            let started = Instant::now();
+            let played = match Self::play_chord(shared, backend, &chord, offset) {
+                Ok(played) => played,
                 Err(e) => {
-                    error!("Could not lock melody: {e}");
-                    return;
+                    warn!("Could not play chord: {e}");
+                    true
                 }
             };
 
-            melody.next_chord();
+            // An interrupted chord is cut short, the speaker may still play one of its notes
+            if !played {
+                Self::play_note(backend, 0)?;
+            }
+
+            playback = shared.lock()?;
+            if let Some(melody) = &mut playback.current {
+                // A paused or interrupted melody continues where its chord was cut short
+                if played {
+                    melody.next_chord();
+                } else {
+                    melody.interrupt_chord(offset + started.elapsed());
+                }
+            }
         }
     }
 
-    fn play_chord(chord: &Chord, chord_duration: Duration) -> Result<()> {
-        // Play the chord by quickly iterating over the notes
-        for note in &chord.notes {
-            beep(*note).map_err(Error::Beep)?;
-            thread::sleep(
-                chord_duration / chord.notes.len().try_into().map_err(|_| Error::Convert)?,
-            );
-        }
+    /// Plays a chord from `offset` into it, returns `false` if the chord was interrupted
+    /// before it was done.
+    fn play_chord(
+        shared: &SharedPlayback,
+        backend: &SharedBackend,
+        chord: &Chord,
+        offset: Duration,
+    ) -> Result<bool> {
+        let mut offset = offset;
+
+        for (note, duration) in Self::chord_tones(chord)? {
+            // Skip the tones that were played before the chord was interrupted
+            let Some(remaining) = duration.checked_sub(offset) else {
+                offset -= duration;
+                continue;
+            };
 
-        // If a note is played out longer, extend the last note
-        if let Some(note) = chord.notes.last() {
-            beep(*note).map_err(Error::Beep)?;
-            thread::sleep(chord.extended_duration);
+            offset = Duration::ZERO;
+            Self::play_note(backend, note)?;
+            if !Self::wait(shared, remaining)? {
+                return Ok(false);
+            }
         }
 
-        // Stop playing the note
-        beep(0).map_err(Error::Beep)?;
-        Ok(())
+        Ok(true)
+    }
+
+    /// The tones a chord consists of, in order, where a frequency of 0 is silence.
+    fn chord_tones(chord: &Chord) -> Result<Vec<(u16, Duration)>> {
+        // A chord without notes is a rest
+        let Some(last) = chord.notes.last() else {
+            return Ok(vec![(0, chord.length())]);
+        };
+
+        // Play the chord by quickly iterating over the notes
+        let note_duration =
+            chord.duration / chord.notes.len().try_into().map_err(|_| Error::Convert)?;
+        let mut tones: Vec<(u16, Duration)> = chord
+            .notes
+            .iter()
+            .map(|note| (*note, note_duration))
+            .collect();
+
+        // If a note is played out longer, extend the last note, then keep quiet for the gap
+        tones.push((*last, chord.extended_duration));
+        tones.push((0, chord.gap));
+        Ok(tones)
+    }
+
+    /// Waits for `duration`, returns `false` if the playback thread was interrupted before.
+    fn wait(shared: &SharedPlayback, duration: Duration) -> Result<bool> {
+        let playback = shared.lock()?;
+        let (_playback, timeout) = shared
+            .changed
+            .wait_timeout_while(playback, duration, |playback| !playback.interrupted())
+            .map_err(|_| Error::LockPoisoned)?;
+
+        Ok(timeout.timed_out())
+    }
+
+    /// Plays a note on the backend, moved into the range the backend supports.
+    fn play_note(backend: &Mutex<dyn ToneBackend>, note: u16) -> Result<()> {
+        let mut backend = backend.lock().map_err(|_| Error::LockPoisoned)?;
+        let note = backend.capabilities().clamp(note);
+        backend.set_frequency(note)
     }
 }
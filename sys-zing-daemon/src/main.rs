@@ -1,42 +1,271 @@
-use log::{info, warn};
+use clap::Parser;
+use log::{error, info, warn};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 use std::fs;
 use std::fs::Permissions;
-use std::io::prelude::*;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::net::{UnixListener, UnixStream};
-use syszingd::MelodyPlayer;
-use zing_protocol::Command;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+use syszingd::backend::BackendKind;
+use syszingd::config::{BackendConfig, Config, DEFAULT_CONFIG_PATH, SocketConfig};
+use syszingd::server::Server;
+use syszingd::{MelodyPlayer, systemd};
+use zing_protocol::Event;
 
-fn decode_stream(mut stream: UnixStream) -> Result<Command, String> {
-    let mut buf: Vec<u8> = Vec::new();
-    let _ = stream.read_to_end(&mut buf).map_err(|e| e.to_string())?;
-    Command::from_bytes(&buf).map_err(|e| e.to_string())
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// The TOML file with the configuration, /etc/zing/syszingd.toml if it exists
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// The socket to listen on, overrides the configuration
+    #[arg(short, long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// The device used to play tones (beep, null or record), overrides the configuration
+    #[arg(short, long)]
+    backend: Option<BackendKind>,
+
+    /// The file the record backend writes its tones to, overrides the configuration
+    #[arg(long, value_name = "FILE")]
+    record_file: Option<PathBuf>,
+
+    /// The seconds a connection may stay silent before it is closed, overrides the configuration
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+
+    /// The largest command that is accepted in bytes, overrides the configuration
+    #[arg(long, value_name = "BYTES")]
+    max_message_size: Option<u32>,
+
+    /// How many connections a single user may have open at the same time, overrides the configuration
+    #[arg(long, value_name = "COUNT")]
+    max_connections: Option<usize>,
+
+    /// The seconds without connections or melodies after which the daemon exits, overrides the configuration
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+}
+
+impl Args {
+    /// Reads the configuration file and applies the options given on the command line.
+    fn config(&self) -> syszingd::Result<Config> {
+        let default = Path::new(DEFAULT_CONFIG_PATH);
+
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None if default.exists() => Config::load(default)?,
+            None => Config::default(),
+        };
+
+        if let Some(socket) = &self.socket {
+            config.socket.path = socket.clone();
+        }
+        if let Some(backend) = self.backend {
+            config.backend.kind = backend;
+        }
+        if let Some(record_file) = &self.record_file {
+            config.backend.record_file = Some(record_file.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            config.connections.timeout = Duration::from_secs(timeout);
+        }
+        if let Some(max_message_size) = self.max_message_size {
+            config.connections.max_message_size = max_message_size;
+        }
+        if let Some(max_connections) = self.max_connections {
+            config.connections.max_connections_per_user = max_connections;
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            config.socket.idle_timeout = Some(Duration::from_secs(idle_timeout));
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
 }
 
 fn main() {
+    let args = Args::parse();
+
     // Create the logger
     env_logger::init();
 
-    // Make sure leftover sockets are removed
-    let _ = fs::remove_file(zing_protocol::SOCKET_PATH);
-    let listener = UnixListener::bind(zing_protocol::SOCKET_PATH).expect("Failed to create socket");
-    fs::set_permissions(zing_protocol::SOCKET_PATH, Permissions::from_mode(0o666))
-        .expect("Failed to set socket permissions");
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Could not start the daemon: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let signals =
+        Signals::new([SIGTERM, SIGINT, SIGHUP]).expect("Failed to register signal handlers");
 
-    let mut melody_player = MelodyPlayer::new();
+    // systemd owns the socket when it started the daemon for a connection
+    let activated = systemd::activated_listener().expect("Failed to take over the systemd socket");
+    let socket = activated.is_none().then(|| config.socket.path.clone());
+    let listener = match activated {
+        Some(listener) => {
+            info!("Using the socket passed by systemd");
+            listener
+        }
+        None => bind(&config.socket),
+    };
+
+    info!("Using the {} backend", config.backend.kind);
+    let backend = config
+        .backend
+        .kind
+        .create(config.backend.record_file.as_deref())
+        .expect("Failed to create backend");
+
+    let player = MelodyPlayer::new(backend)
+        .with_busy_policy(config.playback.busy_policy)
+        .with_limits(config.limits)
+        .with_quiet_hours(config.quiet_hours);
+
+    let events = player.subscribe();
+    thread::spawn(move || report_status(&events));
+
+    let server = Server::new(player)
+        .with_limits(config.connections)
+        .with_policy(config.access)
+        .with_idle_timeout(config.socket.idle_timeout);
+    let server = Arc::new(server);
+
+    let daemon = Daemon {
+        server: Arc::clone(&server),
+        args,
+        socket: config.socket,
+        backend: config.backend,
+        owns_socket: socket.is_some(),
+    };
+    thread::spawn(move || daemon.handle_signals(signals));
 
     info!("Zing daemon running...");
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => match decode_stream(stream) {
-                Ok(command) => {
-                    melody_player.handle_command(command);
+    systemd::ready("Idle");
+    server.run(&listener);
+
+    systemd::stopping("Stopped after being idle");
+    shut_down(&server, socket.as_deref());
+}
+
+/// What the signal handler needs to reload or stop the running daemon.
+struct Daemon {
+    server: Arc<Server>,
+    args: Args,
+
+    /// The settings that only take effect when the daemon starts
+    socket: SocketConfig,
+    backend: BackendConfig,
+
+    /// Whether the daemon created the socket, rather than systemd
+    owns_socket: bool,
+}
+
+impl Daemon {
+    /// Reloads the configuration on SIGHUP, and shuts down on SIGTERM or SIGINT.
+    fn handle_signals(&self, mut signals: Signals) {
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                self.reload();
+                continue;
+            }
+
+            info!("Received signal {signal}, shutting down");
+            systemd::stopping("Shutting down");
+
+            let socket = self.owns_socket.then_some(self.socket.path.as_path());
+            shut_down(&self.server, socket);
+            std::process::exit(0);
+        }
+    }
+
+    /// Applies the configuration file again, the queue and the current melody are kept.
+    fn reload(&self) {
+        info!("Reloading the configuration");
+        systemd::reloading();
+
+        match self.args.config() {
+            Ok(config) => {
+                if config.socket != self.socket || config.backend != self.backend {
+                    warn!("Changes to the socket and backend take effect after a restart");
+                }
+
+                if let Err(e) = self.server.reconfigure(config.connections, config.access) {
+                    error!("Could not apply the configuration: {e}");
                 }
-                Err(e) => {
-                    warn!("Could not decode stream: {e}");
+
+                match self.server.player().lock() {
+                    Ok(mut player) => player.reconfigure(
+                        config.playback.busy_policy,
+                        config.limits,
+                        config.quiet_hours,
+                    ),
+                    Err(_) => {
+                        error!("Could not apply the configuration: the player lock is poisoned")
+                    }
                 }
-            },
-            Err(e) => warn!("Connection failed: {e}"),
+
+                info!("Configuration reloaded");
+            }
+            Err(e) => error!("Kept the previous configuration: {e}"),
+        }
+
+        systemd::ready("Configuration reloaded");
+    }
+}
+
+/// Silences the speaker and removes the socket the daemon created, if any.
+fn shut_down(server: &Server, socket: Option<&Path>) {
+    if let Err(e) = server.shutdown() {
+        error!("Could not stop playback: {e}");
+    }
+
+    if let Some(socket) = socket {
+        let _ = fs::remove_file(socket);
+    }
+}
+
+/// Creates the socket described by the configuration.
+fn bind(socket: &SocketConfig) -> UnixListener {
+    // Make sure leftover sockets are removed
+    let _ = fs::remove_file(&socket.path);
+    let listener = UnixListener::bind(&socket.path).expect("Failed to create socket");
+    fs::set_permissions(&socket.path, Permissions::from_mode(socket.mode))
+        .expect("Failed to set socket permissions");
+    if let Some(group) = socket.group {
+        std::os::unix::fs::chown(&socket.path, None, Some(group))
+            .expect("Failed to set socket group");
+    }
+
+    info!("Listening on {}", socket.path.display());
+    listener
+}
+
+/// Keeps the status shown by `systemctl status` up to date with what the speaker does.
+fn report_status(events: &Receiver<Event>) {
+    let mut playing = String::new();
+
+    for event in events {
+        match event {
+            Event::MelodyStarted {
+                client, priority, ..
+            } => {
+                playing = format!("Playing a melody with {priority} priority for {client}");
+                systemd::status(&playing);
+            }
+            Event::Paused => systemd::status("Paused"),
+            Event::Resumed => systemd::status(&playing),
+            Event::Finished | Event::Stopped => systemd::status("Idle"),
+            Event::ChordAdvanced { .. } | Event::Preempted { .. } => {}
         }
     }
 }
@@ -1,28 +1,34 @@
 use crate::{Error, Result};
-use beep::beep;
 use std::time::Duration;
-use zing_protocol::{Chord, PlayData};
+use zing_protocol::{Chord, Client, PlayData, Priority};
 
 pub struct Melody {
-    chord_duration: Duration,
     chords: Vec<Chord>,
     position: usize,
+
+    /// How much of the current chord was played before it was interrupted
+    chord_offset: Duration,
+
     is_playing: bool,
     was_stopped: bool,
+    priority: Priority,
+    client: Client,
 }
 
 impl Melody {
-    pub fn from_data(data: PlayData) -> Result<Self> {
+    pub fn from_data(data: PlayData, client: Client) -> Result<Self> {
         if data.chords.is_empty() {
             return Err(Error::NoChordsProvided);
         }
 
         Ok(Self {
             position: 0,
+            chord_offset: Duration::ZERO,
             is_playing: false,
             was_stopped: false,
-            chord_duration: data.chord_duration,
             chords: data.chords,
+            priority: data.priority,
+            client,
         })
     }
 
@@ -37,12 +43,11 @@ impl Melody {
 
     pub fn pause(&mut self) {
         self.is_playing = false;
-        let _ = beep(0);
     }
 
     #[must_use]
     pub fn is_finished(&self) -> bool {
-        self.position >= self.chords.len() - 1
+        self.position >= self.chords.len()
     }
 
     #[must_use]
@@ -54,11 +59,24 @@ impl Melody {
         self.is_playing = false;
         self.was_stopped = true;
         self.position = 0;
-        let _ = beep(0);
+        self.chord_offset = Duration::ZERO;
     }
 
     pub fn next_chord(&mut self) {
-        self.position = (self.position + 1) % self.chords.len();
+        self.position = (self.position + 1).min(self.chords.len());
+        self.chord_offset = Duration::ZERO;
+    }
+
+    /// How much of the current chord has already been played.
+    #[must_use]
+    pub fn chord_offset(&self) -> Duration {
+        self.chord_offset
+    }
+
+    /// Remembers how much of the current chord was played when it was interrupted, so it
+    /// continues from there.
+    pub fn interrupt_chord(&mut self, offset: Duration) {
+        self.chord_offset = offset;
     }
 
     #[must_use]
@@ -66,8 +84,55 @@ impl Melody {
         self.chords[self.position].clone()
     }
 
+    /// The client that requested this melody.
+    #[must_use]
+    pub fn client(&self) -> Client {
+        self.client
+    }
+
+    /// How important this melody is compared to others.
+    #[must_use]
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// The index of the chord that is currently being played.
+    #[must_use]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The total time it takes to play the melody from the start.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.chords.iter().map(Chord::length).sum()
+    }
+
+    /// The time taken by the longest chord of the melody.
+    #[must_use]
+    pub fn longest_chord(&self) -> Duration {
+        self.chords
+            .iter()
+            .map(Chord::length)
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The amount of chords in the melody.
+    #[must_use]
+    pub fn chord_count(&self) -> usize {
+        self.chords.len()
+    }
+
+    /// The time spent on the chords before the current position.
+    #[must_use]
+    pub fn elapsed(&self) -> Duration {
+        self.chords[..self.position].iter().map(Chord::length).sum()
+    }
+
+    /// The time left to play, including the current chord.
     #[must_use]
-    pub fn get_chord_duration(&self) -> Duration {
-        self.chord_duration
+    pub fn remaining(&self) -> Duration {
+        self.chords[self.position..].iter().map(Chord::length).sum()
     }
 }
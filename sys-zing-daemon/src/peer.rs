@@ -0,0 +1,173 @@
+use crate::{Error, Result};
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::{mem, ptr};
+use zing_protocol::Client;
+
+/// Looks up the process on the other end of a socket connection.
+///
+/// The credentials are provided by the kernel (`SO_PEERCRED`), so they cannot be forged
+/// by the connecting process.
+///
+/// # Errors
+/// Returns an error if the credentials of the peer cannot be read.
+pub fn peer_client(stream: &UnixStream) -> Result<Client> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut length =
+        libc::socklen_t::try_from(mem::size_of::<libc::ucred>()).map_err(|_| Error::Convert)?;
+
+    // SAFETY: the buffer and length describe a valid `ucred` owned by this function
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut credentials).cast(),
+            &raw mut length,
+        )
+    };
+
+    if result != 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+
+    Ok(Client {
+        pid: credentials.pid,
+        uid: credentials.uid,
+        gid: credentials.gid,
+    })
+}
+
+/// Looks up every group a user belongs to, including the given primary group.
+///
+/// Users that are not in the user database only belong to their primary group.
+///
+/// # Errors
+/// Returns an error if the user or group database cannot be read.
+pub fn user_groups(uid: u32, gid: u32) -> Result<Vec<u32>> {
+    let Some(name) = user_name(uid)? else {
+        return Ok(vec![gid]);
+    };
+
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+    loop {
+        let mut count = c_int::try_from(groups.len()).map_err(|_| Error::Convert)?;
+
+        // SAFETY: the buffer holds `count` groups and the name is a valid C string
+        let result =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &raw mut count) };
+        let count = usize::try_from(count).map_err(|_| Error::Convert)?;
+
+        if result >= 0 {
+            groups.truncate(count);
+            return Ok(groups);
+        }
+
+        // The buffer was too small, the required size has been stored in `count`
+        groups.resize(count.max(groups.len() * 2), 0);
+    }
+}
+
+/// Looks up the id of a user by name, `None` if there is no such user.
+///
+/// # Errors
+/// Returns an error if the user database cannot be read.
+pub fn user_id(name: &str) -> Result<Option<u32>> {
+    let name = CString::new(name).map_err(|_| Error::Convert)?;
+
+    // SAFETY: a zeroed `passwd` is a valid value, it is only read once it has been filled
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let found = lookup(|buffer, result: &mut *mut libc::passwd| {
+        // SAFETY: all pointers point to live values owned by the caller
+        unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &raw mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        }
+    })?;
+
+    Ok(found.then_some(passwd.pw_uid))
+}
+
+/// Looks up the id of a group by name, `None` if there is no such group.
+///
+/// # Errors
+/// Returns an error if the group database cannot be read.
+pub fn group_id(name: &str) -> Result<Option<u32>> {
+    let name = CString::new(name).map_err(|_| Error::Convert)?;
+
+    // SAFETY: a zeroed `group` is a valid value, it is only read once it has been filled
+    let mut group: libc::group = unsafe { mem::zeroed() };
+    let found = lookup(|buffer, result: &mut *mut libc::group| {
+        // SAFETY: all pointers point to live values owned by the caller
+        unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &raw mut group,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        }
+    })?;
+
+    Ok(found.then_some(group.gr_gid))
+}
+
+fn user_name(uid: u32) -> Result<Option<CString>> {
+    // SAFETY: a zeroed `passwd` is a valid value, it is only read once it has been filled
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = Vec::new();
+    let found = lookup_into(&mut buffer, |buffer, result: &mut *mut libc::passwd| {
+        // SAFETY: all pointers point to live values owned by the caller
+        unsafe {
+            libc::getpwuid_r(
+                uid,
+                &raw mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        }
+    })?;
+
+    // SAFETY: the name points into `buffer`, which is still alive
+    Ok(found.then(|| unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned()))
+}
+
+/// Calls one of the reentrant `get*_r` functions, growing its buffer until it fits.
+fn lookup<T>(call: impl FnMut(&mut [c_char], &mut *mut T) -> c_int) -> Result<bool> {
+    lookup_into(&mut Vec::new(), call)
+}
+
+fn lookup_into<T>(
+    buffer: &mut Vec<c_char>,
+    mut call: impl FnMut(&mut [c_char], &mut *mut T) -> c_int,
+) -> Result<bool> {
+    buffer.resize(1024, 0);
+
+    loop {
+        let mut result = ptr::null_mut();
+        let error = call(buffer, &mut result);
+
+        if error == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        if error != 0 {
+            return Err(Error::Io(std::io::Error::from_raw_os_error(error)));
+        }
+
+        return Ok(!result.is_null());
+    }
+}
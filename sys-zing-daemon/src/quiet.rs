@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use zing_protocol::Priority;
+
+/// A time of day with minute precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay {
+    /// Minutes since midnight
+    minutes: u16,
+}
+
+impl TimeOfDay {
+    /// Creates a time of day, `None` if the hour or minute are out of range.
+    #[must_use]
+    pub fn new(hour: u16, minute: u16) -> Option<Self> {
+        (hour < 24 && minute < 60).then_some(Self {
+            minutes: hour * 60 + minute,
+        })
+    }
+
+    /// The current local time of day.
+    #[must_use]
+    pub fn now() -> Self {
+        // SAFETY: `time` accepts a null pointer, `localtime_r` only writes to `local`
+        let local = unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut local: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&raw const now, &raw mut local);
+            local
+        };
+
+        let hour = u16::try_from(local.tm_hour).unwrap_or_default();
+        let minute = u16::try_from(local.tm_min).unwrap_or_default();
+        Self::new(hour, minute).unwrap_or(Self { minutes: 0 })
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid time of day '{s}', expected HH:MM");
+
+        let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+        let hour = hour.parse().map_err(|_| invalid())?;
+        let minute = minute.parse().map_err(|_| invalid())?;
+
+        Self::new(hour, minute).ok_or_else(invalid)
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+/// A period of the day in which only important melodies may play.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHours {
+    pub start: TimeOfDay,
+
+    /// The end of the quiet hours, before the start when they last past midnight
+    pub end: TimeOfDay,
+
+    /// The lowest priority that may still play during the quiet hours
+    #[serde(default = "QuietHours::default_min_priority")]
+    #[serde(deserialize_with = "crate::config::from_str")]
+    pub min_priority: Priority,
+}
+
+impl QuietHours {
+    fn default_min_priority() -> Priority {
+        Priority::High
+    }
+
+    /// Whether `time` falls within the quiet hours.
+    #[must_use]
+    pub fn contains(&self, time: TimeOfDay) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Whether a melody with `priority` may play at `time`.
+    #[must_use]
+    pub fn allows(&self, priority: Priority, time: TimeOfDay) -> bool {
+        priority >= self.min_priority || !self.contains(time)
+    }
+}
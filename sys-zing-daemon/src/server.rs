@@ -0,0 +1,533 @@
+use crate::access::{Permission, Policy};
+use crate::peer::{peer_client, user_groups};
+use crate::{Error, MelodyPlayer, Result};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use zing_protocol::{Client, Command, ErrorReason, Event, PlaybackState, Response, frame};
+
+/// Limits that keep a single misbehaving client from affecting the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// How long a connection may stay silent before it is closed
+    #[serde(deserialize_with = "crate::config::duration")]
+    pub timeout: Duration,
+
+    /// The largest command that is accepted, in bytes
+    pub max_message_size: u32,
+
+    /// How many connections a single user may have open at the same time
+    pub max_connections_per_user: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            max_message_size: 1024 * 1024,
+            max_connections_per_user: 8,
+        }
+    }
+}
+
+/// How often an idle server checks whether it has been idle for long enough.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Accepts connections and answers their commands, every connection on its own thread.
+///
+/// Commands of all connections are handed to a single `MelodyPlayer`, one at a time.
+pub struct Server {
+    player: Arc<Mutex<MelodyPlayer>>,
+    limits: RwLock<Limits>,
+    policy: Arc<RwLock<Policy>>,
+    connections: Arc<Mutex<HashMap<u32, usize>>>,
+
+    /// How long the server may go without connections or melodies before it stops
+    idle_timeout: Option<Duration>,
+}
+
+impl Server {
+    /// Creates a server that plays the melodies it receives on `player`.
+    #[must_use]
+    pub fn new(player: MelodyPlayer) -> Self {
+        Self {
+            player: Arc::new(Mutex::new(player)),
+            limits: RwLock::default(),
+            policy: Arc::default(),
+            connections: Arc::default(),
+            idle_timeout: None,
+        }
+    }
+
+    /// Decides who may connect and what they may do according to `policy`.
+    #[must_use]
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Arc::new(RwLock::new(policy));
+        self
+    }
+
+    /// Applies `limits` to every connection.
+    #[must_use]
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = RwLock::new(limits);
+        self
+    }
+
+    /// The player that plays the melodies of all connections.
+    #[must_use]
+    pub fn player(&self) -> &Mutex<MelodyPlayer> {
+        &self.player
+    }
+
+    /// Replaces the limits and the access policy while the server is running.
+    ///
+    /// The policy applies to the next command of every connection, the limits only to
+    /// connections that are opened afterwards.
+    ///
+    /// # Errors
+    /// Returns an error if a lock is poisoned.
+    pub fn reconfigure(&self, limits: Limits, policy: Policy) -> Result<()> {
+        *self.limits.write().map_err(|_| Error::LockPoisoned)? = limits;
+        *self.policy.write().map_err(|_| Error::LockPoisoned)? = policy;
+        Ok(())
+    }
+
+    /// Stops all melodies and silences the speaker, for when the daemon exits.
+    ///
+    /// # Errors
+    /// Returns an error if the player cannot be stopped or the speaker cannot be silenced.
+    pub fn shutdown(&self) -> Result<()> {
+        self.player
+            .lock()
+            .map_err(|_| Error::LockPoisoned)?
+            .shutdown()
+    }
+
+    /// Stops the server once it has had no connections and nothing to play for `idle_timeout`.
+    #[must_use]
+    pub fn with_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Accepts connections on `listener`, until the server has been idle for its idle timeout.
+    ///
+    /// Without an idle timeout the server never stops.
+    pub fn run(&self, listener: &UnixListener) {
+        let mut idle_since = Instant::now();
+
+        loop {
+            if let Some(idle_timeout) = self.idle_timeout {
+                if self.is_busy() {
+                    idle_since = Instant::now();
+                } else if idle_since.elapsed() >= idle_timeout {
+                    info!("Stopping after being idle for {idle_timeout:?}");
+                    return;
+                }
+
+                match wait_for_connection(listener, idle_timeout.min(IDLE_CHECK_INTERVAL)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        warn!("Could not wait for connections: {e}");
+                        continue;
+                    }
+                }
+            }
+
+            match listener.accept() {
+                Ok((stream, _)) => self.accept(stream),
+                Err(e) => warn!("Connection failed: {e}"),
+            }
+        }
+    }
+
+    /// Whether any connection is open or any melody is playing, paused or queued.
+    fn is_busy(&self) -> bool {
+        let connected = self
+            .connections
+            .lock()
+            .map_or(true, |connections| !connections.is_empty());
+
+        connected
+            || self.player.lock().map_or(true, |player| {
+                !matches!(player.state(), Ok(PlaybackState::Idle))
+            })
+    }
+
+    /// Starts answering a new connection on its own thread.
+    ///
+    /// The client is identified on that thread too, since looking up its groups may wait on
+    /// a slow user database and must not keep other clients from connecting.
+    pub fn accept(&self, mut stream: UnixStream) {
+        let Ok(limits) = self.limits.read().map(|limits| *limits) else {
+            respond(&mut stream, &Response::Error(Error::LockPoisoned.reason()));
+            return;
+        };
+
+        let player = Arc::clone(&self.player);
+        let policy = Arc::clone(&self.policy);
+        let connections = Arc::clone(&self.connections);
+
+        thread::spawn(move || {
+            let Some(session) = Session::admit(&mut stream, &policy, &connections, &limits) else {
+                return;
+            };
+
+            if let Err(e) = session.serve(stream, &player, &policy, &limits) {
+                warn!("Closed connection of {}: {e}", session.client);
+            }
+        });
+    }
+}
+
+/// An open connection, counted against the connection limit of its user until dropped.
+struct Session {
+    client: Client,
+
+    /// The groups the user of the client belongs to, as far as the policy needs them
+    groups: Vec<u32>,
+
+    connections: Arc<Mutex<HashMap<u32, usize>>>,
+}
+
+impl Session {
+    /// Identifies the client on `stream` and opens a session if the policy and the
+    /// connection limit allow it, or tells the client why not.
+    fn admit(
+        stream: &mut UnixStream,
+        policy: &RwLock<Policy>,
+        connections: &Arc<Mutex<HashMap<u32, usize>>>,
+        limits: &Limits,
+    ) -> Option<Self> {
+        let client = match peer_client(stream) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Could not identify client: {e}");
+                respond(stream, &Response::Error(e.reason()));
+                return None;
+            }
+        };
+
+        let Ok(uses_groups) = policy.read().map(|policy| policy.uses_groups()) else {
+            respond(stream, &Response::Error(Error::LockPoisoned.reason()));
+            return None;
+        };
+
+        // Group lookups read the group database, skip them when the policy does not care.
+        // The policy is not locked meanwhile, so a slow lookup cannot hold up a reload.
+        let groups = if uses_groups {
+            match user_groups(client.uid, client.gid) {
+                Ok(groups) => groups,
+                Err(e) => {
+                    warn!("Could not look up the groups of {client}: {e}");
+                    respond(stream, &Response::Error(e.reason()));
+                    return None;
+                }
+            }
+        } else {
+            vec![client.gid]
+        };
+
+        let Ok(policy) = policy.read() else {
+            respond(stream, &Response::Error(Error::LockPoisoned.reason()));
+            return None;
+        };
+
+        if !policy.may_connect(&client, &groups) {
+            warn!("Refused {client}, the access policy does not allow it to connect");
+            respond(stream, &Response::Error(ErrorReason::PermissionDenied));
+            return None;
+        }
+        drop(policy);
+
+        let session = Session::open(connections, client, groups, limits);
+        if session.is_none() {
+            warn!("Refused {client}, it has too many open connections");
+            respond(stream, &Response::Error(ErrorReason::TooManyConnections));
+        }
+
+        session
+    }
+
+    fn open(
+        connections: &Arc<Mutex<HashMap<u32, usize>>>,
+        client: Client,
+        groups: Vec<u32>,
+        limits: &Limits,
+    ) -> Option<Self> {
+        let mut open = connections.lock().ok()?;
+        let count = open.entry(client.uid).or_default();
+
+        if *count >= limits.max_connections_per_user {
+            return None;
+        }
+
+        *count += 1;
+        Some(Self {
+            client,
+            groups,
+            connections: Arc::clone(connections),
+        })
+    }
+
+    /// Answers the commands of the client until it closes the connection.
+    fn serve(
+        &self,
+        mut stream: UnixStream,
+        player: &Mutex<MelodyPlayer>,
+        policy: &RwLock<Policy>,
+        limits: &Limits,
+    ) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(limits.timeout))?;
+        stream.set_write_timeout(Some(limits.timeout))?;
+
+        loop {
+            let message = match frame::read_frame(&mut stream, limits.max_message_size) {
+                Ok(Some(message)) => message,
+                Ok(None) => return Ok(()),
+                Err(zing_protocol::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    debug!("Connection of {} timed out", self.client);
+                    return Ok(());
+                }
+                Err(e @ zing_protocol::Error::FrameTooLarge(_)) => {
+                    refuse(&mut stream, Refusal::Invalid(e.to_string()));
+                    return Ok(());
+                }
+
+                // Clients without frames send a single message and close their side
+                Err(e) => {
+                    refuse(&mut stream, Refusal::Legacy(e.to_string()));
+                    return Ok(());
+                }
+            };
+
+            match decode_message(&message) {
+                Ok(command) => {
+                    info!("{} sent {command}", self.client);
+
+                    let Ok(mut player) = player.lock() else {
+                        respond(&mut stream, &Response::Error(Error::LockPoisoned.reason()));
+                        continue;
+                    };
+
+                    if let Err(e) = self.authorize(&command, &player, policy) {
+                        drop(player);
+                        respond(&mut stream, &Response::Error(e.reason()));
+                        continue;
+                    }
+
+                    if let Command::Subscribe = command {
+                        let events = player.subscribe();
+                        drop(player);
+
+                        respond(&mut stream, &Response::Ok);
+                        return self.stream_events(&mut stream, &events, limits);
+                    }
+
+                    let response = player.handle_command(command, self.client);
+                    drop(player);
+                    respond(&mut stream, &response);
+                }
+                Err(refusal @ Refusal::Invalid(_)) => refuse(&mut stream, refusal),
+                Err(refusal) => {
+                    refuse(&mut stream, refusal);
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Session {
+    /// Checks whether the policy allows the client to send `command` right now.
+    fn authorize(
+        &self,
+        command: &Command,
+        player: &MelodyPlayer,
+        policy: &RwLock<Policy>,
+    ) -> Result<()> {
+        let policy = policy.read().map_err(|_| Error::LockPoisoned)?;
+        let affects_others = player
+            .affected_clients(command)?
+            .iter()
+            .any(|owner| owner.uid != self.client.uid);
+
+        let missing = Permission::required(command, affects_others)
+            .iter()
+            .find(|permission| !policy.allows(&self.client, &self.groups, **permission));
+
+        match missing {
+            Some(permission) => {
+                warn!(
+                    "Denied {command} to {}, it lacks the {permission} permission",
+                    self.client
+                );
+                Err(Error::PermissionDenied)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends events to a subscribed client until it closes the connection.
+    fn stream_events(
+        &self,
+        stream: &mut UnixStream,
+        events: &Receiver<Event>,
+        limits: &Limits,
+    ) -> std::io::Result<()> {
+        info!("{} subscribed to events", self.client);
+
+        loop {
+            let event = match events.recv_timeout(limits.timeout) {
+                Ok(event) => event,
+
+                // Nothing to send, look whether the client is still there
+                Err(RecvTimeoutError::Timeout) if is_closed(stream)? => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            };
+
+            let bytes = Response::Event(event)
+                .as_bytes()
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+
+            if let Err(e) = frame::write_frame(stream, &bytes) {
+                debug!("Subscription of {} ended: {e}", self.client);
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(mut open) = self.connections.lock()
+            && let Some(count) = open.get_mut(&self.client.uid)
+        {
+            *count -= 1;
+
+            if *count == 0 {
+                open.remove(&self.client.uid);
+            }
+        }
+    }
+}
+
+/// Waits until a connection arrives on `listener`, returns `false` if none did in time.
+fn wait_for_connection(listener: &UnixListener, timeout: Duration) -> std::io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: listener.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+
+    // SAFETY: `fd` is a single valid `pollfd` owned by this function
+    match unsafe { libc::poll(&raw mut fd, 1, timeout) } {
+        -1 => {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Whether the other side closed the connection, without waiting for it to send anything.
+///
+/// Subscribed clients have nothing left to say, anything they do send is discarded.
+fn is_closed(mut stream: &UnixStream) -> std::io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let read = stream.read(&mut [0; 64]);
+    stream.set_nonblocking(false)?;
+
+    match read {
+        Ok(read) => Ok(read == 0),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// A command that could not be handled, with the way the refusal should be answered.
+enum Refusal {
+    /// The client predates the protocol header, it only understands a bare response
+    Legacy(String),
+
+    /// The client speaks an unsupported protocol version, answered in that version
+    Version(u16),
+
+    /// The command could not be decoded
+    Invalid(String),
+}
+
+fn decode_message(message: &[u8]) -> std::result::Result<Command, Refusal> {
+    let version = match zing_protocol::message_version(message) {
+        Ok(version) => version,
+        Err(e) => return Err(Refusal::Legacy(e.to_string())),
+    };
+
+    if !zing_protocol::is_supported(version) {
+        return Err(Refusal::Version(version));
+    }
+
+    Command::from_bytes(message).map_err(|e| Refusal::Invalid(e.to_string()))
+}
+
+fn refuse(stream: &mut UnixStream, refusal: Refusal) {
+    match refusal {
+        Refusal::Legacy(e) => {
+            warn!("Refused a client without protocol header: {e}");
+            let response = Response::Error(ErrorReason::InvalidCommand(format!(
+                "This zing is too old for the daemon, protocol version {} or newer is required",
+                zing_protocol::MIN_PROTOCOL_VERSION
+            )));
+
+            // Such clients read the bare response until the connection closes
+            let result = response
+                .as_legacy_bytes()
+                .and_then(|bytes| stream.write_all(&bytes).map_err(zing_protocol::Error::Io));
+
+            if let Err(e) = result {
+                warn!("Could not send response: {e}");
+            }
+        }
+        Refusal::Version(version) => {
+            warn!("Refused a client with protocol version {version}");
+            let response = Response::Error(ErrorReason::IncompatibleVersion {
+                client: version,
+                min_version: zing_protocol::MIN_PROTOCOL_VERSION,
+                max_version: zing_protocol::PROTOCOL_VERSION,
+            });
+            send(stream, response.as_bytes_for(version));
+        }
+        Refusal::Invalid(e) => {
+            info!("Could not decode command: {e}");
+            respond(stream, &Response::Error(ErrorReason::InvalidCommand(e)));
+        }
+    }
+}
+
+fn respond(stream: &mut UnixStream, response: &Response) {
+    send(stream, response.as_bytes());
+}
+
+fn send(stream: &mut UnixStream, bytes: zing_protocol::Result<Vec<u8>>) {
+    if let Err(e) = bytes.and_then(|bytes| frame::write_frame(stream, &bytes)) {
+        warn!("Could not send response: {e}");
+    }
+}
@@ -0,0 +1,52 @@
+use crate::{Error, Result};
+use log::warn;
+use sd_notify::NotifyState;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixListener;
+
+/// Takes over the listening socket systemd opened for the daemon, if it was socket activated.
+///
+/// # Errors
+/// Returns an error if the environment describing the sockets is invalid, or if systemd
+/// passed more than one socket.
+pub fn activated_listener() -> Result<Option<UnixListener>> {
+    let mut fds = sd_notify::listen_fds().map_err(Error::Io)?;
+
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+
+    if fds.next().is_some() {
+        return Err(Error::TooManySockets);
+    }
+
+    // SAFETY: systemd passed the descriptor to this process, nothing else owns it
+    Ok(Some(unsafe { UnixListener::from_raw_fd(fd) }))
+}
+
+/// Tells systemd the daemon is ready to accept connections.
+pub fn ready(status: &str) {
+    notify(&[NotifyState::Ready, NotifyState::Status(status)]);
+}
+
+/// Updates the status line shown by `systemctl status`.
+pub fn status(status: &str) {
+    notify(&[NotifyState::Status(status)]);
+}
+
+/// Tells systemd the daemon is reloading its configuration, `ready` tells it when it is done.
+pub fn reloading() {
+    notify(&[NotifyState::Reloading]);
+}
+
+/// Tells systemd the daemon is shutting down.
+pub fn stopping(status: &str) {
+    notify(&[NotifyState::Stopping, NotifyState::Status(status)]);
+}
+
+/// Does nothing when the daemon was not started by systemd.
+fn notify(states: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, states) {
+        warn!("Could not notify systemd: {e}");
+    }
+}
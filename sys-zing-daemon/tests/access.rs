@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use syszingd::access::{Permission, Policy};
+use syszingd::backend::RecordingBackend;
+use syszingd::{Error, MelodyPlayer};
+use zing_protocol::{Chord, Client, Command, PlayData, Priority};
+
+const ALICE: Client = Client {
+    pid: 10,
+    uid: 1000,
+    gid: 1000,
+};
+
+const BOB: Client = Client {
+    pid: 20,
+    uid: 1001,
+    gid: 1001,
+};
+
+const ROOT: Client = Client {
+    pid: 30,
+    uid: 0,
+    gid: 0,
+};
+
+#[test]
+fn the_default_policy_allows_everything() {
+    let policy = Policy::default();
+
+    assert!(policy.may_connect(&ALICE, &[1000]));
+    assert!(policy.allows(&ALICE, &[1000], Permission::ControlOthers));
+}
+
+#[test]
+fn only_listed_users_and_groups_may_connect() {
+    let policy = Policy::from_toml("allow_users = [1000]\nallow_groups = [63]").unwrap();
+
+    assert!(policy.may_connect(&ALICE, &[1000]));
+    assert!(policy.may_connect(&BOB, &[1001, 63]));
+    assert!(!policy.may_connect(&BOB, &[1001]));
+    assert!(policy.may_connect(&ROOT, &[0]));
+}
+
+#[test]
+fn controlling_others_needs_to_be_granted() {
+    let policy = Policy::from_toml("[group_permissions]\n4 = [\"control_others\"]").unwrap();
+
+    assert!(policy.allows(&ALICE, &[1000], Permission::Control));
+    assert!(!policy.allows(&ALICE, &[1000], Permission::ControlOthers));
+    assert!(policy.allows(&BOB, &[1001, 4], Permission::ControlOthers));
+    assert!(policy.allows(&ROOT, &[0], Permission::ControlOthers));
+}
+
+#[test]
+fn permissions_can_be_restricted() {
+    let policy = Policy::from_toml("permissions = [\"status\"]").unwrap();
+
+    assert!(policy.allows(&ALICE, &[1000], Permission::Status));
+    assert!(!policy.allows(&ALICE, &[1000], Permission::Play));
+}
+
+#[test]
+fn stopping_a_melody_of_someone_else_needs_control_others() {
+    assert_eq!(
+        Permission::required(&Command::Stop, false),
+        [Permission::Control]
+    );
+    assert_eq!(
+        Permission::required(&Command::Stop, true),
+        [Permission::ControlOthers]
+    );
+    assert_eq!(
+        Permission::required(&Command::Status, true),
+        [Permission::Status]
+    );
+}
+
+fn play(priority: Priority) -> Command {
+    Command::Play(PlayData {
+        chords: vec![Chord {
+            duration: Duration::from_secs(10),
+            notes: vec![440],
+            ..Chord::default()
+        }],
+        priority,
+    })
+}
+
+#[test]
+fn replacing_a_melody_of_someone_else_needs_control_others() {
+    let mut player = MelodyPlayer::new(Arc::new(Mutex::new(RecordingBackend::new())));
+    player.handle_command(play(Priority::Normal), ALICE);
+
+    // Playing at the same or a higher priority ends or interrupts the melody of Alice
+    assert_eq!(
+        player.affected_clients(&play(Priority::Normal)).unwrap(),
+        [ALICE]
+    );
+    assert_eq!(
+        player.affected_clients(&play(Priority::High)).unwrap(),
+        [ALICE]
+    );
+    assert!(
+        player
+            .affected_clients(&play(Priority::Low))
+            .unwrap()
+            .is_empty()
+    );
+
+    let required = Permission::required(&play(Priority::Normal), true);
+    assert_eq!(required, [Permission::Play, Permission::ControlOthers]);
+
+    // Bob may play, but not over the melody of Alice
+    let policy = Policy::from_toml("permissions = [\"play\"]").unwrap();
+    assert!(policy.allows(&BOB, &[1001], Permission::Play));
+    assert!(
+        !required
+            .iter()
+            .all(|permission| policy.allows(&BOB, &[1001], *permission))
+    );
+
+    player.handle_command(Command::Stop, ALICE);
+}
+
+#[test]
+fn groups_can_be_named() {
+    let policy = Policy::from_toml("allow_groups = [\"root\"]").unwrap();
+    assert!(policy.may_connect(&BOB, &[0]));
+
+    let unknown = Policy::from_toml("allow_groups = [\"no-such-group-exists\"]");
+    assert!(matches!(unknown, Err(Error::UnknownGroup(_))));
+}
+
+#[test]
+fn unknown_settings_are_refused() {
+    assert!(matches!(
+        Policy::from_toml("allow_user = [1000]"),
+        Err(Error::Toml(_))
+    ));
+}
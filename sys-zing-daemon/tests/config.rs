@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::time::Duration;
+use syszingd::BusyPolicy;
+use syszingd::access::Permission;
+use syszingd::backend::BackendKind;
+use syszingd::config::Config;
+use syszingd::quiet::{QuietHours, TimeOfDay};
+use zing_protocol::{Client, Priority};
+
+const EXAMPLE: &str = r#"
+[socket]
+path = "/run/zing/zing.sock"
+mode = 0o660
+group = 0
+
+[backend]
+kind = "record"
+record_file = "/tmp/tones.log"
+
+[playback]
+busy_policy = "drop"
+
+[limits]
+max_chord_duration = "2s"
+max_melody_duration = "1m"
+
+[connections]
+timeout = "10s"
+max_connections_per_user = 2
+
+[quiet_hours]
+start = "22:00"
+end = "07:30"
+
+[access]
+permissions = ["play", "status"]
+"#;
+
+fn time(hour: u16, minute: u16) -> TimeOfDay {
+    TimeOfDay::new(hour, minute).unwrap()
+}
+
+#[test]
+fn an_empty_configuration_uses_the_defaults() {
+    let config = Config::from_toml("").unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.socket.path, zing_protocol::socket_path());
+    assert_eq!(config.socket.mode, 0o666);
+    assert_eq!(config.backend.kind, BackendKind::Beep);
+    assert_eq!(config.limits.max_chord_duration, None);
+    assert!(config.quiet_hours.is_none());
+}
+
+#[test]
+fn every_section_is_read() {
+    let config = Config::from_toml(EXAMPLE).unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.socket.path, Path::new("/run/zing/zing.sock"));
+    assert_eq!(config.socket.mode, 0o660);
+    assert_eq!(config.socket.group, Some(0));
+    assert_eq!(config.backend.kind, BackendKind::Record);
+    assert_eq!(config.playback.busy_policy, BusyPolicy::Drop);
+    assert_eq!(
+        config.limits.max_chord_duration,
+        Some(Duration::from_secs(2))
+    );
+    assert_eq!(
+        config.limits.max_melody_duration,
+        Some(Duration::from_secs(60))
+    );
+    assert_eq!(config.connections.timeout, Duration::from_secs(10));
+    assert_eq!(config.connections.max_connections_per_user, 2);
+
+    let quiet_hours = config.quiet_hours.unwrap();
+    assert_eq!(quiet_hours.start, time(22, 0));
+    assert_eq!(quiet_hours.min_priority, Priority::High);
+
+    let client = Client {
+        pid: 1,
+        uid: 1000,
+        gid: 1000,
+    };
+    assert!(config.access.allows(&client, &[1000], Permission::Play));
+    assert!(!config.access.allows(&client, &[1000], Permission::Queue));
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    assert!(Config::from_toml("[socket]\npermissions = 0o600").is_err());
+    assert!(Config::from_toml("[speaker]").is_err());
+}
+
+#[test]
+fn invalid_values_are_rejected() {
+    assert!(Config::from_toml("[backend]\nkind = \"piano\"").is_err());
+    assert!(Config::from_toml("[limits]\nmax_chord_duration = \"soon\"").is_err());
+    assert!(Config::from_toml("[quiet_hours]\nstart = \"25:00\"\nend = \"07:00\"").is_err());
+}
+
+#[test]
+fn inconsistent_settings_fail_validation() {
+    let invalid = |text: &str| Config::from_toml(text).unwrap().validate().is_err();
+
+    assert!(invalid("[backend]\nkind = \"record\""));
+    assert!(invalid("[socket]\nmode = 0o7777"));
+    assert!(invalid("[socket]\nidle_timeout = \"0s\""));
+    assert!(invalid("[connections]\ntimeout = \"0s\""));
+    assert!(invalid("[connections]\nmax_connections_per_user = 0"));
+    assert!(invalid("[quiet_hours]\nstart = \"22:00\"\nend = \"22:00\""));
+}
+
+#[test]
+fn quiet_hours_may_last_past_midnight() {
+    let quiet_hours = QuietHours {
+        start: time(22, 0),
+        end: time(7, 0),
+        min_priority: Priority::High,
+    };
+
+    assert!(quiet_hours.contains(time(23, 30)));
+    assert!(quiet_hours.contains(time(0, 0)));
+    assert!(quiet_hours.contains(time(6, 59)));
+    assert!(!quiet_hours.contains(time(7, 0)));
+    assert!(!quiet_hours.contains(time(12, 0)));
+
+    assert!(!quiet_hours.allows(Priority::Normal, time(23, 0)));
+    assert!(quiet_hours.allows(Priority::High, time(23, 0)));
+    assert!(quiet_hours.allows(Priority::Low, time(12, 0)));
+}
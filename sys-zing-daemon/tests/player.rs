@@ -0,0 +1,541 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use syszingd::backend::{RecordingBackend, ToneEvent, ToneLog};
+use syszingd::{BusyPolicy, MelodyLimits, MelodyPlayer};
+use zing_protocol::{
+    Chord, Client, Command, ErrorReason, Event, PlayData, PlaybackState, Priority, Response,
+};
+
+const CHORD_DURATION: Duration = Duration::from_millis(40);
+const TOLERANCE: Duration = Duration::from_millis(25);
+
+const CLIENT: Client = Client {
+    pid: 1,
+    uid: 1000,
+    gid: 1000,
+};
+
+fn player() -> (MelodyPlayer, ToneLog) {
+    let backend = RecordingBackend::new();
+    let log = backend.log();
+    (MelodyPlayer::new(Arc::new(Mutex::new(backend))), log)
+}
+
+fn melody(notes: &[u16], priority: Priority) -> PlayData {
+    PlayData {
+        chords: notes
+            .iter()
+            .map(|note| Chord {
+                duration: CHORD_DURATION,
+                notes: vec![*note],
+                ..Chord::default()
+            })
+            .collect(),
+        priority,
+    }
+}
+
+/// The notes that were played, in order, without the silences in between.
+fn played_notes(log: &ToneLog) -> Vec<u16> {
+    let mut notes: Vec<u16> = log
+        .events()
+        .iter()
+        .map(|event| event.frequency)
+        .filter(|frequency| *frequency != 0)
+        .collect();
+
+    notes.dedup();
+    notes
+}
+
+/// The moments at which each note started playing.
+fn note_starts(log: &ToneLog) -> Vec<ToneEvent> {
+    let mut previous = 0;
+
+    log.events()
+        .into_iter()
+        .filter(|event| {
+            let started = event.frequency != 0 && event.frequency != previous;
+            previous = event.frequency;
+            started
+        })
+        .collect()
+}
+
+/// Whether the speaker was silenced while the first `note` should still have sounded.
+fn cut_short(log: &ToneLog, note: u16, duration: Duration) -> bool {
+    let events = log.events();
+    let Some(start) = events.iter().find(|event| event.frequency == note) else {
+        return false;
+    };
+
+    events
+        .iter()
+        .any(|event| event.frequency == 0 && event.at > start.at && event.at < start.at + duration)
+}
+
+fn wait_until_idle(player: &MelodyPlayer) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+
+    while player.state().unwrap() != PlaybackState::Idle {
+        assert!(Instant::now() < deadline, "melody did not finish in time");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn plays_every_chord_in_order() {
+    let (mut player, log) = player();
+
+    let response = player.handle_command(
+        Command::Play(melody(&[440, 550, 660], Priority::Normal)),
+        CLIENT,
+    );
+    assert!(matches!(response, Response::State(PlaybackState::Playing)));
+
+    wait_until_idle(&player);
+    assert_eq!(played_notes(&log), vec![440, 550, 660]);
+    assert_eq!(log.events().last().unwrap().frequency, 0);
+}
+
+#[test]
+fn chords_follow_the_chord_duration() {
+    let (mut player, log) = player();
+
+    player.handle_command(
+        Command::Play(melody(&[440, 550, 660, 770], Priority::Normal)),
+        CLIENT,
+    );
+    wait_until_idle(&player);
+
+    let starts = note_starts(&log);
+    assert_eq!(starts.len(), 4);
+
+    for pair in starts.windows(2) {
+        let gap = pair[1].at - pair[0].at;
+        assert!(
+            gap >= CHORD_DURATION && gap <= CHORD_DURATION + TOLERANCE,
+            "chord took {gap:?}"
+        );
+    }
+}
+
+#[test]
+fn extended_duration_holds_the_last_note() {
+    let (mut player, log) = player();
+
+    let mut data = melody(&[440, 550], Priority::Normal);
+    data.chords[0].extended_duration = Duration::from_millis(80);
+
+    player.handle_command(Command::Play(data), CLIENT);
+    wait_until_idle(&player);
+
+    let starts = note_starts(&log);
+    let gap = starts[1].at - starts[0].at;
+    let expected = CHORD_DURATION + Duration::from_millis(80);
+    assert!(
+        gap >= expected && gap <= expected + TOLERANCE,
+        "chord took {gap:?}"
+    );
+}
+
+#[test]
+fn every_chord_has_its_own_duration() {
+    let (mut player, log) = player();
+
+    let mut data = melody(&[440, 550, 660], Priority::Normal);
+    data.chords[0].duration = Duration::from_millis(90);
+
+    player.handle_command(Command::Play(data), CLIENT);
+    wait_until_idle(&player);
+
+    let starts = note_starts(&log);
+    let first = starts[1].at - starts[0].at;
+    let second = starts[2].at - starts[1].at;
+    assert!(
+        first >= Duration::from_millis(90) && first <= Duration::from_millis(90) + TOLERANCE,
+        "chord took {first:?}"
+    );
+    assert!(
+        second >= CHORD_DURATION && second <= CHORD_DURATION + TOLERANCE,
+        "chord took {second:?}"
+    );
+}
+
+#[test]
+fn rests_and_gaps_are_silent() {
+    let (mut player, log) = player();
+
+    let mut data = melody(&[440, 550], Priority::Normal);
+    data.chords[0].gap = Duration::from_millis(30);
+    data.chords.insert(
+        1,
+        Chord {
+            duration: Duration::from_millis(60),
+            ..Chord::default()
+        },
+    );
+
+    player.handle_command(Command::Play(data), CLIENT);
+    wait_until_idle(&player);
+
+    let events = log.events();
+    let first = events
+        .iter()
+        .position(|event| event.frequency == 440)
+        .unwrap();
+    let next = events
+        .iter()
+        .position(|event| event.frequency == 550)
+        .unwrap();
+    let silence = first
+        + events[first..]
+            .iter()
+            .position(|event| event.frequency == 0)
+            .unwrap();
+    assert!(
+        events[silence..next]
+            .iter()
+            .all(|event| event.frequency == 0)
+    );
+
+    // The note stops after its own duration, the gap and the rest follow in silence
+    let note = events[silence].at - events[first].at;
+    let quiet = events[next].at - events[silence].at;
+    let expected = Duration::from_millis(90);
+    assert!(
+        note >= CHORD_DURATION && note <= CHORD_DURATION + TOLERANCE,
+        "note took {note:?}"
+    );
+    assert!(
+        quiet >= expected && quiet <= expected + TOLERANCE,
+        "silence took {quiet:?}"
+    );
+}
+
+#[test]
+fn pause_silences_until_resumed() {
+    let (mut player, log) = player();
+    let notes: Vec<u16> = (1..=8).map(|note| note * 100).collect();
+
+    player.handle_command(Command::Play(melody(&notes, Priority::Normal)), CLIENT);
+    thread::sleep(Duration::from_millis(90));
+
+    let response = player.handle_command(Command::Pause, CLIENT);
+    assert!(matches!(response, Response::State(PlaybackState::Paused)));
+
+    // The chord that was playing is cut short, give the playback thread time to notice
+    thread::sleep(CHORD_DURATION + TOLERANCE);
+    let paused_events = log.events();
+    thread::sleep(Duration::from_millis(150));
+
+    assert_eq!(log.events(), paused_events);
+    assert_eq!(paused_events.last().unwrap().frequency, 0);
+    assert!(played_notes(&log).len() < notes.len());
+
+    let response = player.handle_command(Command::Resume, CLIENT);
+    assert!(matches!(response, Response::State(PlaybackState::Playing)));
+
+    wait_until_idle(&player);
+    assert_eq!(played_notes(&log), notes);
+}
+
+#[test]
+fn paused_chords_continue_where_they_were_cut_short() {
+    let (mut player, log) = player();
+    let mut data = melody(&[100, 200, 300], Priority::Normal);
+    for chord in &mut data.chords {
+        chord.duration = Duration::from_millis(200);
+    }
+
+    player.handle_command(Command::Play(data), CLIENT);
+    thread::sleep(Duration::from_millis(250));
+    player.handle_command(Command::Pause, CLIENT);
+    thread::sleep(Duration::from_millis(100));
+    player.handle_command(Command::Resume, CLIENT);
+    wait_until_idle(&player);
+
+    // The second chord sounds before and after the pause, and is not skipped
+    let starts = note_starts(&log);
+    let notes: Vec<u16> = starts.iter().map(|event| event.frequency).collect();
+    assert_eq!(notes, vec![100, 200, 200, 300]);
+
+    // Only the part of the chord that was left is played after resuming
+    let rest = starts[3].at - starts[2].at;
+    let expected = Duration::from_millis(150);
+    assert!(
+        rest + TOLERANCE >= expected && rest <= expected + TOLERANCE,
+        "the rest of the chord took {rest:?}"
+    );
+}
+
+#[test]
+fn stop_silences_and_ends_playback() {
+    let (mut player, log) = player();
+    let notes: Vec<u16> = (1..=8).map(|note| note * 100).collect();
+
+    player.handle_command(Command::Play(melody(&notes, Priority::Normal)), CLIENT);
+    thread::sleep(Duration::from_millis(90));
+
+    let response = player.handle_command(Command::Stop, CLIENT);
+    assert!(matches!(response, Response::State(PlaybackState::Idle)));
+
+    let stopped_events = log.events();
+    thread::sleep(Duration::from_millis(150));
+
+    assert_eq!(log.events(), stopped_events);
+    assert_eq!(stopped_events.last().unwrap().frequency, 0);
+    assert!(played_notes(&log).len() < notes.len());
+}
+
+#[test]
+fn status_reports_progress() {
+    let (mut player, _log) = player();
+
+    player.handle_command(
+        Command::Play(melody(&[440, 550, 660, 770], Priority::High)),
+        CLIENT,
+    );
+    player.handle_command(Command::Pause, CLIENT);
+
+    let Response::Status(status) = player.handle_command(Command::Status, CLIENT) else {
+        panic!("expected a status response");
+    };
+
+    assert_eq!(status.state, PlaybackState::Paused);
+    assert_eq!(status.total, 4);
+    assert_eq!(status.priority, Priority::High);
+    assert_eq!(status.client, Some(CLIENT));
+    assert_eq!(status.elapsed + status.remaining, CHORD_DURATION * 4);
+    assert_eq!(
+        status.elapsed,
+        CHORD_DURATION * status.position.try_into().unwrap()
+    );
+
+    player.handle_command(Command::Stop, CLIENT);
+
+    let Response::Status(status) = player.handle_command(Command::Status, CLIENT) else {
+        panic!("expected a status response");
+    };
+
+    assert_eq!(status.state, PlaybackState::Idle);
+    assert_eq!(status.client, None);
+}
+
+#[test]
+fn empty_melodies_are_refused() {
+    let (mut player, log) = player();
+
+    let response = player.handle_command(Command::Play(melody(&[], Priority::Normal)), CLIENT);
+
+    assert!(matches!(
+        response,
+        Response::Error(ErrorReason::NoChordsProvided)
+    ));
+    assert!(played_notes(&log).is_empty());
+}
+
+#[test]
+fn queued_melodies_play_one_after_the_other() {
+    let (mut player, log) = player();
+
+    player.handle_command(Command::Play(melody(&[100, 200], Priority::Normal)), CLIENT);
+    player.handle_command(
+        Command::Enqueue(melody(&[300, 400], Priority::Normal)),
+        CLIENT,
+    );
+    player.handle_command(
+        Command::Enqueue(melody(&[500, 600], Priority::Normal)),
+        CLIENT,
+    );
+
+    let Response::Queue(queue) = player.handle_command(Command::ListQueue, CLIENT) else {
+        panic!("expected a queue response");
+    };
+    assert_eq!(queue.len(), 2);
+
+    wait_until_idle(&player);
+    assert_eq!(played_notes(&log), vec![100, 200, 300, 400, 500, 600]);
+}
+
+#[test]
+fn urgent_melodies_interrupt_and_resume() {
+    let (mut player, log) = player();
+    let notes: Vec<u16> = (1..=6).map(|note| note * 100).collect();
+
+    player.handle_command(Command::Play(melody(&notes, Priority::Low)), CLIENT);
+    thread::sleep(Duration::from_millis(60));
+    player.handle_command(Command::Play(melody(&[901, 902], Priority::High)), CLIENT);
+
+    wait_until_idle(&player);
+
+    let played = played_notes(&log);
+    let split = played.iter().position(|note| *note == 901).unwrap();
+
+    assert_eq!(played[split..split + 2], [901, 902]);
+
+    // The interrupted chord continues afterwards, so it may sound on both sides
+    let mut resumed = [&played[..split], &played[split + 2..]].concat();
+    resumed.dedup();
+    assert_eq!(resumed, notes);
+}
+
+#[test]
+fn less_important_melodies_can_be_dropped() {
+    let (player, log) = player();
+    let mut player = player.with_busy_policy(BusyPolicy::Drop);
+
+    player.handle_command(
+        Command::Play(melody(&[440, 550, 660], Priority::High)),
+        CLIENT,
+    );
+    thread::sleep(CHORD_DURATION / 2);
+    let response = player.handle_command(Command::Play(melody(&[100], Priority::Low)), CLIENT);
+
+    assert!(matches!(response, Response::Error(ErrorReason::Busy)));
+    wait_until_idle(&player);
+
+    // The more important melody plays on as if nothing happened
+    assert_eq!(played_notes(&log), [440, 550, 660]);
+    for note in [440, 550, 660] {
+        assert!(
+            !cut_short(&log, note, CHORD_DURATION),
+            "{note} was cut short"
+        );
+    }
+}
+
+#[test]
+fn queueing_does_not_cut_the_current_note_short() {
+    let (mut player, log) = player();
+    let mut data = melody(&[440], Priority::Normal);
+    data.chords[0].duration = Duration::from_millis(200);
+
+    player.handle_command(Command::Play(data), CLIENT);
+    thread::sleep(Duration::from_millis(50));
+    let response =
+        player.handle_command(Command::Enqueue(melody(&[550], Priority::Normal)), CLIENT);
+
+    assert!(matches!(response, Response::State(PlaybackState::Playing)));
+    wait_until_idle(&player);
+
+    assert_eq!(played_notes(&log), [440, 550]);
+    assert!(!cut_short(&log, 440, Duration::from_millis(200)));
+}
+
+#[test]
+fn subscribers_follow_the_melody() {
+    let (mut player, _log) = player();
+    let events = player.subscribe();
+
+    player.handle_command(Command::Play(melody(&[440, 550], Priority::Normal)), CLIENT);
+    wait_until_idle(&player);
+
+    let events: Vec<Event> = events.try_iter().collect();
+    assert!(matches!(
+        events[0],
+        Event::MelodyStarted {
+            client: CLIENT,
+            chords: 2,
+            ..
+        }
+    ));
+    assert!(
+        matches!(&events[1], Event::ChordAdvanced { position: 0, chord } if chord.notes == [440])
+    );
+    assert!(
+        matches!(&events[2], Event::ChordAdvanced { position: 1, chord } if chord.notes == [550])
+    );
+    assert_eq!(events[3], Event::Finished);
+    assert_eq!(events.len(), 4);
+}
+
+#[test]
+fn subscribers_see_interruptions() {
+    let (mut player, _log) = player();
+    let events = player.subscribe();
+
+    player.handle_command(
+        Command::Play(melody(&[440, 440, 440, 440], Priority::Low)),
+        CLIENT,
+    );
+    player.handle_command(Command::Play(melody(&[880], Priority::High)), CLIENT);
+    player.handle_command(Command::Stop, CLIENT);
+
+    let events: Vec<Event> = events.try_iter().collect();
+    assert!(events.contains(&Event::Preempted {
+        priority: Priority::High
+    }));
+    assert_eq!(events.last(), Some(&Event::Stopped));
+}
+
+#[test]
+fn melodies_beyond_the_limits_are_refused() {
+    let (player, log) = player();
+    let mut player = player.with_limits(MelodyLimits {
+        max_chord_duration: Some(CHORD_DURATION),
+        max_melody_duration: Some(CHORD_DURATION * 2),
+    });
+
+    let mut long_chord = melody(&[440], Priority::Normal);
+    long_chord.chords[0].duration = CHORD_DURATION * 2;
+    let response = player.handle_command(Command::Play(long_chord), CLIENT);
+    assert!(matches!(
+        response,
+        Response::Error(ErrorReason::ChordTooLong(_))
+    ));
+
+    let response = player.handle_command(
+        Command::Enqueue(melody(&[440, 550, 660], Priority::Normal)),
+        CLIENT,
+    );
+    assert!(matches!(
+        response,
+        Response::Error(ErrorReason::MelodyTooLong(_))
+    ));
+
+    assert!(played_notes(&log).is_empty());
+}
+
+#[test]
+fn shutting_down_silences_the_speaker() {
+    let (mut player, log) = player();
+
+    player.handle_command(Command::Play(melody(&[440, 550], Priority::Normal)), CLIENT);
+    player.handle_command(Command::Enqueue(melody(&[660], Priority::Normal)), CLIENT);
+    player.shutdown().unwrap();
+
+    assert_eq!(player.state().unwrap(), PlaybackState::Idle);
+    assert!(player.queue().unwrap().is_empty());
+    assert_eq!(log.events().last().map(|event| event.frequency), Some(0));
+}
+
+#[test]
+fn long_chords_are_stopped_right_away() {
+    let (mut player, log) = player();
+    let mut long = melody(&[440], Priority::Normal);
+    long.chords[0].duration = Duration::from_secs(10);
+
+    player.handle_command(Command::Play(long), CLIENT);
+    thread::sleep(CHORD_DURATION);
+
+    let started = Instant::now();
+    player.handle_command(Command::Stop, CLIENT);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(log.events().last().unwrap().frequency, 0);
+}
+
+#[test]
+fn paused_melodies_wake_up_when_resumed() {
+    let (mut player, log) = player();
+
+    player.handle_command(Command::Play(melody(&[440, 550], Priority::Normal)), CLIENT);
+    player.handle_command(Command::Pause, CLIENT);
+    thread::sleep(CHORD_DURATION * 3);
+    assert_eq!(player.state().unwrap(), PlaybackState::Paused);
+
+    player.handle_command(Command::Resume, CLIENT);
+    wait_until_idle(&player);
+    assert_eq!(played_notes(&log).last(), Some(&550));
+}
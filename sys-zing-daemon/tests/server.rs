@@ -0,0 +1,201 @@
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use syszingd::MelodyPlayer;
+use syszingd::access::Policy;
+use syszingd::backend::NullBackend;
+use syszingd::server::{Limits, Server};
+use zing_protocol::{
+    Chord, Command, Connection, ErrorReason, Event, PlayData, PlaybackState, Priority, Response,
+    frame,
+};
+
+/// Starts a server on a socket of its own and returns the path of that socket.
+fn server(name: &str, limits: Limits) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("zing-{name}-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server =
+        Server::new(MelodyPlayer::new(Arc::new(Mutex::new(NullBackend)))).with_limits(limits);
+    thread::spawn(move || server.run(&listener));
+
+    path
+}
+
+#[test]
+fn idle_connections_do_not_block_others() {
+    let path = server("idle", Limits::default());
+
+    // Connects without ever sending a command
+    let _idle = UnixStream::connect(&path).unwrap();
+
+    let mut connection = Connection::connect_to(&path).unwrap();
+    for _ in 0..3 {
+        let response = connection.request(&Command::Stop).unwrap();
+        assert!(matches!(response, Response::State(PlaybackState::Idle)));
+    }
+}
+
+#[test]
+fn silent_connections_are_closed() {
+    let path = server(
+        "timeout",
+        Limits {
+            timeout: Duration::from_millis(50),
+            ..Limits::default()
+        },
+    );
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    let closed = frame::read_frame(&mut stream, frame::MAX_FRAME_LENGTH).unwrap();
+    assert!(closed.is_none());
+}
+
+#[test]
+fn connections_are_limited_per_user() {
+    let path = server(
+        "limit",
+        Limits {
+            max_connections_per_user: 2,
+            ..Limits::default()
+        },
+    );
+
+    let mut first = Connection::connect_to(&path).unwrap();
+    let _second = Connection::connect_to(&path).unwrap();
+
+    // Make sure the server counted both connections before the third one arrives
+    first.request(&Command::Status).unwrap();
+
+    let mut third = Connection::connect_to(&path).unwrap();
+    let response = third.request(&Command::Status).unwrap();
+    assert!(matches!(
+        response,
+        Response::Error(ErrorReason::TooManyConnections)
+    ));
+}
+
+#[test]
+fn oversized_commands_are_refused() {
+    let path = server(
+        "size",
+        Limits {
+            max_message_size: 16,
+            ..Limits::default()
+        },
+    );
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    frame::write_frame(&mut stream, &[0; 32]).unwrap();
+
+    let message = frame::read_frame(&mut stream, frame::MAX_FRAME_LENGTH)
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        Response::from_bytes(&message).unwrap(),
+        Response::Error(ErrorReason::InvalidCommand(_))
+    ));
+}
+
+#[test]
+fn subscriptions_stream_events() {
+    let path = server("subscribe", Limits::default());
+    let mut events = Connection::connect_to(&path).unwrap().subscribe().unwrap();
+
+    let melody = PlayData {
+        chords: vec![
+            Chord {
+                duration: Duration::from_millis(200),
+                notes: vec![440],
+                ..Chord::default()
+            };
+            2
+        ],
+        priority: Priority::Normal,
+    };
+
+    let mut connection = Connection::connect_to(&path).unwrap();
+    connection.request(&Command::Play(melody)).unwrap();
+    connection.request(&Command::Stop).unwrap();
+
+    assert!(matches!(
+        events.next().unwrap().unwrap(),
+        Event::MelodyStarted { chords: 2, .. }
+    ));
+
+    // The first chord may or may not have started before the melody was stopped
+    let event = events.next().unwrap().unwrap();
+    let event = match event {
+        Event::ChordAdvanced { .. } => events.next().unwrap().unwrap(),
+        event => event,
+    };
+    assert_eq!(event, Event::Stopped);
+}
+
+#[test]
+fn idle_servers_stop() {
+    let path = std::env::temp_dir().join(format!("zing-idle-stop-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server = Server::new(MelodyPlayer::new(Arc::new(Mutex::new(NullBackend))))
+        .with_idle_timeout(Some(Duration::from_millis(200)));
+    let running = thread::spawn(move || server.run(&listener));
+
+    // An open connection keeps the server running
+    let mut connection = Connection::connect_to(&path).unwrap();
+    connection.request(&Command::Status).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    assert!(!running.is_finished());
+
+    drop(connection);
+    let started = std::time::Instant::now();
+    while !running.is_finished() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn reconfiguring_applies_to_new_connections() {
+    let path = std::env::temp_dir().join(format!("zing-reload-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    let server = Arc::new(Server::new(MelodyPlayer::new(Arc::new(Mutex::new(
+        NullBackend,
+    )))));
+    let running = Arc::clone(&server);
+    thread::spawn(move || running.run(&listener));
+
+    let mut before = Connection::connect_to(&path).unwrap();
+    before.request(&Command::Status).unwrap();
+
+    let limits = Limits {
+        max_message_size: 16,
+        ..Limits::default()
+    };
+    server.reconfigure(limits, Policy::default()).unwrap();
+
+    // The connection that was already open keeps its limits
+    let response = before.request(&Command::Status).unwrap();
+    assert!(matches!(response, Response::Status(_)));
+
+    let mut stream = UnixStream::connect(&path).unwrap();
+    frame::write_frame(&mut stream, &[0; 32]).unwrap();
+
+    let message = frame::read_frame(&mut stream, frame::MAX_FRAME_LENGTH)
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        Response::from_bytes(&message).unwrap(),
+        Response::Error(ErrorReason::InvalidCommand(_))
+    ));
+}
@@ -0,0 +1,940 @@
+use crate::{Error, Result, midi_note_frequency};
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The semitones of the natural notes above C, indexed by letter from C to B.
+const SEMITONES: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// The letters that receive sharps in a key signature, in order (F C G D A E B).
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+/// The letters that receive flats in a key signature, in order (B E A D G C F).
+const FLAT_ORDER: [usize; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+/// The tempo used when a tune does not specify one, in quarter notes per minute.
+const DEFAULT_BPM: u64 = 120;
+
+/// The most `>` or `<` of a broken rhythm, `>>>` makes a note 15/8 as long.
+const MAX_BROKEN_RHYTHM: u32 = 3;
+
+/// Characters that decorate the next note without changing its pitch or length.
+const DECORATIONS: &str = "~.HLMOPSTuv";
+
+/// A length as a fraction of a whole note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Length {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl Length {
+    const fn new(numerator: u64, denominator: u64) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    fn times(self, other: Length) -> Result<Length> {
+        let numerator = self.numerator.checked_mul(other.numerator);
+        let denominator = self.denominator.checked_mul(other.denominator);
+        let (numerator, denominator) = numerator.zip(denominator).ok_or_else(out_of_range)?;
+        let divisor = gcd(numerator, denominator).max(1);
+
+        Ok(Length::new(numerator / divisor, denominator / divisor))
+    }
+
+    fn plus(self, other: Length) -> Result<Length> {
+        let numerator = self
+            .numerator
+            .checked_mul(other.denominator)
+            .zip(other.numerator.checked_mul(self.denominator))
+            .and_then(|(a, b)| a.checked_add(b));
+        let denominator = self.denominator.checked_mul(other.denominator);
+        let (numerator, denominator) = numerator.zip(denominator).ok_or_else(out_of_range)?;
+
+        Length::new(numerator, denominator).times(Length::new(1, 1))
+    }
+
+    /// Whether the length is shorter than `other`.
+    fn is_shorter_than(self, other: Length) -> bool {
+        u128::from(self.numerator) * u128::from(other.denominator)
+            < u128::from(other.numerator) * u128::from(self.denominator)
+    }
+
+    /// How long the length takes at `tempo`.
+    fn duration(self, tempo: &Tempo) -> Result<Duration> {
+        let nanos = u128::from(self.numerator)
+            .checked_mul(u128::from(tempo.beat.denominator))
+            .and_then(|nanos| nanos.checked_mul(60_000_000_000));
+        let per = u128::from(self.denominator)
+            .checked_mul(u128::from(tempo.beat.numerator))
+            .and_then(|per| per.checked_mul(u128::from(tempo.bpm)));
+
+        nanos
+            .zip(per)
+            .and_then(|(nanos, per)| u64::try_from(nanos / per).ok())
+            .map(Duration::from_nanos)
+            .ok_or_else(out_of_range)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// How many beats of which length fit in a minute.
+#[derive(Debug, Clone, Copy)]
+struct Tempo {
+    beat: Length,
+    bpm: u64,
+}
+
+/// The time signature, `None` for free meter.
+#[derive(Debug, Clone, Copy)]
+struct Meter {
+    beats: u64,
+    unit: u64,
+}
+
+impl Meter {
+    fn bar(self) -> Length {
+        Length::new(self.beats, self.unit)
+    }
+
+    /// Whether the beats are divided in three, like 6/8 or 9/8.
+    fn is_compound(self) -> bool {
+        self.beats > 3 && self.beats.is_multiple_of(3)
+    }
+}
+
+/// Notes that are squeezed into the time of fewer notes, like triplets.
+struct Tuplet {
+    factor: Length,
+    remaining: u64,
+}
+
+/// Parses a tune in ABC notation into a sequence of `Chord`s.
+///
+/// ABC is a text format for (mostly folk) tunes. A tune starts with a header of fields,
+/// one per line, and ends with the key (`K:`) after which the notes follow.
+///
+/// - `X:` and `T:` number and name the tune and are ignored, like other information fields.
+/// - `M:` sets the meter (`6/8`, `C` or `C|`), `L:` the unit note length (`1/8`) and
+///   `Q:` the tempo (`1/4=120`). The fields can also change the tune halfway, on a line of
+///   their own or inline like `[L:1/16]`.
+/// - `K:` sets the key (`G`, `Bb`, `F#m`, `Ddor`, `none`), which sharpens or flattens
+///   notes unless they carry their own accidental (`^` sharp, `_` flat, `=` natural).
+///   Accidentals last until the end of the bar.
+/// - Notes are `C`–`B` from middle C upwards, `c`–`b` the octave above, raised by `'` and
+///   lowered by `,`. A number multiplies the unit length and `/` divides it (`A2`, `A/2`,
+///   `A3/2`). `>` and `<` lengthen one note at the expense of the next.
+/// - `z` and `x` are rests, `Z` rests for whole bars, `[CEG]` plays notes together and
+///   `-` ties a note to the next one.
+/// - Bar lines, repeats (`|:` and `:|`), first and second endings (`|1` and `:|2`) and
+///   tuplets (`(3abc`) are followed.
+///
+/// Only the first tune of a file and the first voice of a tune are played. Chord symbols,
+/// decorations, grace notes, lyrics and comments are ignored.
+///
+/// # Errors
+/// Returns an error if a field or note cannot be parsed.
+///
+/// # Example
+/// ```text
+/// X:1
+/// T:Speed the Plough
+/// M:4/4
+/// L:1/8
+/// Q:1/4=160
+/// K:G
+/// |:GABc dedB|dedB dedB|c2ec B2dB|c2A2 A2BA|
+///   GABc dedB|dedB dedB|c2ec B2dB|A2F2 G4:|
+/// ```
+pub fn chords_from_abc(abc: &str) -> Result<Vec<Chord>> {
+    let mut parser = Parser::new();
+    let mut in_tune = !abc.lines().any(is_tune_start);
+
+    for line in abc.lines() {
+        let line = strip_comment(line);
+
+        // Lines before the first tune belong to the file header
+        if !in_tune {
+            in_tune = is_tune_start(line);
+            if !in_tune {
+                continue;
+            }
+        }
+
+        // An empty line ends the tune, unless it is between the header and the tune
+        if line.trim().is_empty() {
+            if parser.in_body {
+                break;
+            }
+            continue;
+        }
+
+        match field(line) {
+            // The next tune starts without an empty line in between
+            Some(('X', _)) if parser.in_body => break,
+            Some((name, value)) => parser.field(name, value)?,
+            None => parser.body(line)?,
+        }
+    }
+
+    Ok(parser.chords)
+}
+
+fn is_tune_start(line: &str) -> bool {
+    line.starts_with("X:")
+}
+
+fn strip_comment(line: &str) -> &str {
+    line.split_once('%').map_or(line, |(line, _)| line)
+}
+
+/// Splits a field line like `K:G` into its name and value.
+fn field(line: &str) -> Option<(char, &str)> {
+    let mut chars = line.chars();
+    let name = chars.next().filter(char::is_ascii_alphabetic)?;
+
+    (chars.next() == Some(':')).then(|| (name, line[2..].trim()))
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidAbc(message.into())
+}
+
+fn out_of_range() -> Error {
+    invalid("a note is too long or too short")
+}
+
+struct Parser {
+    /// Set once the key field ended the header
+    in_body: bool,
+
+    unit: Option<Length>,
+    meter: Option<Meter>,
+    tempo: Option<Tempo>,
+
+    /// The accidental of every letter from C to B according to the key, in semitones
+    key: [i32; 7],
+
+    /// Accidentals written earlier in the bar, by letter and octave
+    bar_accidentals: HashMap<(usize, i32), i32>,
+
+    /// The voice that is played, and whether the notes being read belong to it
+    voice: Option<String>,
+    in_voice: bool,
+
+    chords: Vec<Chord>,
+
+    /// Where a repeated section starts, and where its first ending starts
+    repeat_start: usize,
+    first_ending: Option<usize>,
+
+    tuplet: Option<Tuplet>,
+
+    /// The factor a broken rhythm (`>` or `<`) applies to the next note
+    broken: Option<Length>,
+
+    /// Whether the last note is tied to the next one
+    tied: bool,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Self {
+            in_body: false,
+            unit: None,
+            meter: Some(Meter { beats: 4, unit: 4 }),
+            tempo: None,
+            key: [0; 7],
+            bar_accidentals: HashMap::new(),
+            voice: None,
+            in_voice: true,
+            chords: Vec::new(),
+            repeat_start: 0,
+            first_ending: None,
+            tuplet: None,
+            broken: None,
+            tied: false,
+        }
+    }
+
+    /// The unit note length, which depends on the meter unless it was set.
+    fn unit(&self) -> Length {
+        self.unit.unwrap_or_else(|| match self.meter {
+            Some(meter) if meter.bar().is_shorter_than(Length::new(3, 4)) => Length::new(1, 16),
+            _ => Length::new(1, 8),
+        })
+    }
+
+    fn tempo(&self) -> Tempo {
+        self.tempo.unwrap_or(Tempo {
+            beat: Length::new(1, 4),
+            bpm: DEFAULT_BPM,
+        })
+    }
+
+    fn field(&mut self, name: char, value: &str) -> Result<()> {
+        match name {
+            'K' => {
+                self.key = parse_key(value)?;
+                self.in_body = true;
+            }
+            'L' => self.unit = Some(parse_length(value)?),
+            'M' => self.meter = parse_meter(value)?,
+            'Q' => {
+                if let Some(tempo) = parse_tempo(value, self.unit())? {
+                    self.tempo = Some(tempo);
+                }
+            }
+            'V' => {
+                let id = value.split_whitespace().next().unwrap_or_default();
+                let voice = self.voice.get_or_insert_with(|| id.to_string());
+                self.in_voice = voice == id;
+            }
+
+            // Titles, composers, lyrics and the like do not change the sound
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn body(&mut self, line: &str) -> Result<()> {
+        // Lines that are not fields before the key start the tune anyway
+        self.in_body = true;
+
+        let mut chars = line.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '"' | '!' | '+' => {
+                    chars.next();
+                    skip_until(&mut chars, c)?;
+                }
+                '{' => {
+                    chars.next();
+                    skip_until(&mut chars, '}')?;
+                }
+                '(' => {
+                    chars.next();
+                    if chars.peek().is_some_and(char::is_ascii_digit) {
+                        self.tuplet(&mut chars)?;
+                    }
+                }
+                '[' => self.bracket(&mut chars)?,
+                '|' | ':' => self.bar(&mut chars)?,
+                '-' => {
+                    chars.next();
+                    self.tied = true;
+                }
+                '>' | '<' => self.broken_rhythm(&mut chars)?,
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let (note, length) = self.note(&mut chars)?;
+                    self.push(vec![note], length)?;
+                }
+                'z' | 'x' => {
+                    chars.next();
+                    let length = self.unit().times(parse_multiplier(&mut chars)?)?;
+                    self.push(Vec::new(), length)?;
+                }
+                'Z' | 'X' => {
+                    chars.next();
+                    let bars = take_number(&mut chars).unwrap_or(1);
+                    let meter = self
+                        .meter
+                        .ok_or_else(|| invalid("bar rests need a meter"))?;
+                    self.push(Vec::new(), meter.bar().times(Length::new(bars, 1))?)?;
+                }
+                '&' => return Err(invalid("voice overlays (&) are not supported")),
+                c if c.is_whitespace() || "`\\y)$".contains(c) || DECORATIONS.contains(c) => {
+                    chars.next();
+                }
+                c => return Err(invalid(format!("unexpected '{c}'"))),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a chord, an inline field, an ending or a bar line that starts with `[`.
+    fn bracket(&mut self, chars: &mut Peekable<Chars>) -> Result<()> {
+        chars.next();
+
+        let inline_field = chars.clone().nth(1) == Some(':');
+
+        match chars.peek() {
+            Some('|') => self.bar(chars),
+            Some(c) if c.is_ascii_digit() => {
+                self.ending(chars);
+                Ok(())
+            }
+            Some(c) if c.is_ascii_alphabetic() && inline_field => {
+                let name = chars.next().unwrap_or_default();
+                chars.next();
+
+                let value: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                self.field(name, value.trim())
+            }
+            _ => self.chord(chars),
+        }
+    }
+
+    fn chord(&mut self, chars: &mut Peekable<Chars>) -> Result<()> {
+        let mut notes = Vec::new();
+        let mut length = None;
+
+        loop {
+            match chars.peek() {
+                Some(']') => {
+                    chars.next();
+                    break;
+                }
+                Some('^' | '_' | '=' | 'A'..='G' | 'a'..='g') => {
+                    let (note, note_length) = self.note(chars)?;
+                    notes.push(note);
+                    length.get_or_insert(note_length);
+                }
+                Some(c) if c.is_whitespace() || DECORATIONS.contains(*c) || *c == '-' => {
+                    chars.next();
+                }
+                Some(c) => return Err(invalid(format!("unexpected '{c}' in chord"))),
+                None => return Err(invalid("unclosed chord")),
+            }
+        }
+
+        let length = length
+            .ok_or_else(|| invalid("empty chord"))?
+            .times(parse_multiplier(chars)?)?;
+        self.push(notes, length)
+    }
+
+    /// Reads a note with its accidental, octave and length, returning its frequency.
+    fn note(&mut self, chars: &mut Peekable<Chars>) -> Result<(u16, Length)> {
+        let mut accidental = None;
+        while let Some(c) = chars.next_if(|c| matches!(c, '^' | '_' | '=')) {
+            accidental = Some(match c {
+                '^' => accidental.unwrap_or(0) + 1,
+                '_' => accidental.unwrap_or(0) - 1,
+                _ => 0,
+            });
+        }
+
+        let letter = chars
+            .next()
+            .ok_or_else(|| invalid("accidental without note"))?;
+        let index = "CDEFGAB"
+            .find(letter.to_ascii_uppercase())
+            .ok_or_else(|| invalid(format!("'{letter}' is not a note")))?;
+
+        let mut octave = if letter.is_ascii_uppercase() { 4 } else { 5 };
+        while let Some(c) = chars.next_if(|c| matches!(c, '\'' | ',')) {
+            octave += if c == '\'' { 1 } else { -1 };
+        }
+
+        let accidental = match accidental {
+            Some(accidental) => {
+                self.bar_accidentals.insert((index, octave), accidental);
+                accidental
+            }
+            None => self
+                .bar_accidentals
+                .get(&(index, octave))
+                .copied()
+                .unwrap_or(self.key[index]),
+        };
+
+        let midi_note = (octave + 1) * 12 + SEMITONES[index] + accidental;
+        let midi_note = u8::try_from(midi_note)
+            .ok()
+            .filter(|note| *note < 128)
+            .ok_or_else(|| invalid(format!("'{letter}' is out of range")))?;
+
+        let length = self.unit().times(parse_multiplier(chars)?)?;
+        Ok((midi_note_frequency(midi_note), length))
+    }
+
+    /// Adds a note, rest or chord, applying tuplets, broken rhythm and ties.
+    fn push(&mut self, notes: Vec<u16>, mut length: Length) -> Result<()> {
+        if let Some(tuplet) = &mut self.tuplet {
+            length = length.times(tuplet.factor)?;
+            tuplet.remaining -= 1;
+
+            if tuplet.remaining == 0 {
+                self.tuplet = None;
+            }
+        }
+
+        if let Some(factor) = self.broken.take() {
+            length = length.times(factor)?;
+        }
+
+        let tied = std::mem::take(&mut self.tied);
+        if !self.in_voice {
+            return Ok(());
+        }
+
+        let duration = length.duration(&self.tempo())?;
+        if let Some(previous) = self.chords.last_mut()
+            && tied
+            && previous.notes == notes
+        {
+            previous.extended_duration = previous
+                .extended_duration
+                .checked_add(duration)
+                .ok_or_else(out_of_range)?;
+            return Ok(());
+        }
+
+        self.chords.push(Chord {
+            duration,
+            notes,
+            ..Chord::default()
+        });
+        Ok(())
+    }
+
+    /// Reads `(p:q:r`, which plays the next `r` notes in the time of `q` notes.
+    fn tuplet(&mut self, chars: &mut Peekable<Chars>) -> Result<()> {
+        let notes = take_number(chars).filter(|notes| *notes > 1);
+        let notes = notes.ok_or_else(|| invalid("a tuplet needs at least two notes"))?;
+
+        let mut time = None;
+        let mut count = None;
+        if chars.next_if_eq(&':').is_some() {
+            time = take_number(chars);
+            if chars.next_if_eq(&':').is_some() {
+                count = take_number(chars);
+            }
+        }
+
+        let time = time.unwrap_or(match notes {
+            3 | 6 => 2,
+            2 | 4 | 8 => 3,
+            _ if self.meter.is_some_and(Meter::is_compound) => 3,
+            _ => 2,
+        });
+
+        self.tuplet = Some(Tuplet {
+            factor: Length::new(time, notes),
+            remaining: count.unwrap_or(notes).max(1),
+        });
+        Ok(())
+    }
+
+    /// Reads `>` or `<`, which lengthen the previous note and shorten the next, or the
+    /// other way around.
+    fn broken_rhythm(&mut self, chars: &mut Peekable<Chars>) -> Result<()> {
+        let Some(direction) = chars.next() else {
+            return Ok(());
+        };
+
+        let mut count = 1;
+        while chars.next_if_eq(&direction).is_some() {
+            count += 1;
+        }
+
+        if count > MAX_BROKEN_RHYTHM {
+            return Err(invalid(format!(
+                "broken rhythm with more than {MAX_BROKEN_RHYTHM} '{direction}'"
+            )));
+        }
+
+        // `>` makes the previous note 3/2 as long, `>>` 7/4 and `>>>` 15/8
+        let halves = 1_u32 << count;
+        let shortened = (1, halves);
+        let lengthened = (2 * halves - 1, halves);
+        let (previous, next) = if direction == '>' {
+            (lengthened, shortened)
+        } else {
+            (shortened, lengthened)
+        };
+
+        if self.in_voice
+            && let Some(chord) = self.chords.last_mut()
+        {
+            chord.duration = chord.duration * previous.0 / previous.1;
+        }
+
+        self.broken = Some(Length::new(u64::from(next.0), u64::from(next.1)));
+        Ok(())
+    }
+
+    /// Reads a bar line, following the repeats it marks.
+    fn bar(&mut self, chars: &mut Peekable<Chars>) -> Result<()> {
+        let mut bar = String::new();
+        while let Some(c) = chars.next_if(|c| matches!(c, '|' | ':' | ']')) {
+            bar.push(c);
+        }
+
+        if !bar.contains('|') && bar != "::" {
+            return Err(invalid(format!("unexpected '{bar}'")));
+        }
+
+        self.bar_accidentals.clear();
+
+        let (end, start) = match bar.as_str() {
+            "::" => (true, true),
+            _ => (bar.starts_with(':'), bar.ends_with(':')),
+        };
+
+        if end && self.in_voice {
+            let until = self.first_ending.take().unwrap_or(self.chords.len());
+            let repeated = self.chords[self.repeat_start.min(until)..until].to_vec();
+            self.chords.extend(repeated);
+            self.repeat_start = self.chords.len();
+        }
+
+        if start && self.in_voice {
+            self.repeat_start = self.chords.len();
+            self.first_ending = None;
+        }
+
+        if chars.peek().is_some_and(char::is_ascii_digit) {
+            self.ending(chars);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the number of an ending like `1` or `2`, after a bar line or `[`.
+    fn ending(&mut self, chars: &mut Peekable<Chars>) {
+        let number = take_number(chars);
+        while chars
+            .next_if(|c| matches!(c, ',' | '-') || c.is_ascii_digit())
+            .is_some()
+        {}
+
+        if number == Some(1) && self.in_voice {
+            self.first_ending = Some(self.chords.len());
+        }
+    }
+}
+
+fn skip_until(chars: &mut Peekable<Chars>, end: char) -> Result<()> {
+    if chars.any(|c| c == end) {
+        Ok(())
+    } else {
+        Err(invalid(format!("missing closing '{end}'")))
+    }
+}
+
+/// Reads the length of a note relative to the unit length, like `2`, `/2`, `3/2` or `//`.
+fn parse_multiplier(chars: &mut Peekable<Chars>) -> Result<Length> {
+    let numerator = take_number(chars).unwrap_or(1);
+    let mut denominator = 1;
+
+    while chars.next_if_eq(&'/').is_some() {
+        denominator = take_number(chars)
+            .unwrap_or(2)
+            .checked_mul(denominator)
+            .ok_or_else(out_of_range)?;
+    }
+
+    if numerator == 0 || denominator == 0 {
+        return Err(invalid("notes cannot last zero time"));
+    }
+
+    Length::new(numerator, denominator).times(Length::new(1, 1))
+}
+
+/// Parses a length like `1/8`.
+fn parse_length(value: &str) -> Result<Length> {
+    let parse = |number: &str| number.trim().parse::<u64>().ok().filter(|n| *n > 0);
+
+    let length = match value.split_once('/') {
+        Some((numerator, denominator)) => parse(numerator).zip(parse(denominator)),
+        None => parse(value).map(|numerator| (numerator, 1)),
+    };
+
+    length
+        .map(|(numerator, denominator)| Length::new(numerator, denominator))
+        .ok_or_else(|| invalid(format!("invalid length '{value}'")))
+}
+
+/// Parses a meter like `6/8`, `C`, `C|` or `none`.
+fn parse_meter(value: &str) -> Result<Option<Meter>> {
+    match value {
+        "C" => return Ok(Some(Meter { beats: 4, unit: 4 })),
+        "C|" => return Ok(Some(Meter { beats: 2, unit: 2 })),
+        "" | "none" => return Ok(None),
+        _ => {}
+    }
+
+    let meter = || invalid(format!("invalid meter '{value}'"));
+    let (beats, unit) = value.split_once('/').ok_or_else(meter)?;
+
+    // Complex meters like 2+3/8 add up their beats
+    let beats = beats
+        .trim_matches(|c| c == '(' || c == ')')
+        .split('+')
+        .try_fold(0_u64, |total, beat| {
+            let beat = beat.trim().parse::<u64>().map_err(|_| meter())?;
+            total.checked_add(beat).ok_or_else(meter)
+        })?;
+    let unit = unit.trim().parse::<u64>().map_err(|_| meter())?;
+
+    if beats == 0 || unit == 0 {
+        return Err(meter());
+    }
+
+    Ok(Some(Meter { beats, unit }))
+}
+
+/// Parses a tempo like `1/4=120`, `"Allegro" 3/8=80` or just `120` beats of `unit`.
+///
+/// Tempos that are only described in words are ignored.
+fn parse_tempo(value: &str, unit: Length) -> Result<Option<Tempo>> {
+    // Leave out any text between quotes
+    let value: String = value.split('"').step_by(2).collect();
+    let tempo = || invalid(format!("invalid tempo '{}'", value.trim()));
+
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beats, bpm)) => {
+            let beat = beats
+                .split_whitespace()
+                .map(parse_length)
+                .try_fold(Length::new(0, 1), |total, beat| total.plus(beat?))?;
+            (beat, bpm)
+        }
+        None if value.trim().is_empty() => return Ok(None),
+        None => (unit, value.as_str()),
+    };
+
+    let bpm = bpm
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|bpm| *bpm > 0)
+        .ok_or_else(tempo)?;
+
+    if beat.numerator == 0 {
+        return Err(tempo());
+    }
+
+    Ok(Some(Tempo { beat, bpm }))
+}
+
+/// Parses a key like `G`, `Bb`, `F#m`, `D dorian`, `none` or `D ^g`, returning the
+/// accidental of every letter.
+fn parse_key(value: &str) -> Result<[i32; 7]> {
+    let mut key = [0; 7];
+    let mut tokens = value.split_whitespace().peekable();
+    let unknown = || invalid(format!("unknown key '{value}'"));
+
+    let tonic = tokens.next_if(|token| !token.starts_with(['^', '_', '=']) && !token.contains('='));
+
+    if let Some(tonic) = tonic.filter(|tonic| !tonic.eq_ignore_ascii_case("none")) {
+        let mut chars = tonic.chars();
+
+        // Highland bagpipe keys are played without signature
+        let fifths = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('H') => None,
+            Some('C') => Some(0),
+            Some('D') => Some(2),
+            Some('E') => Some(4),
+            Some('F') => Some(-1),
+            Some('G') => Some(1),
+            Some('A') => Some(3),
+            Some('B') => Some(5),
+            _ => return Err(unknown()),
+        };
+
+        if let Some(mut fifths) = fifths {
+            let mut mode = chars.as_str();
+            if let Some(rest) = mode.strip_prefix('#') {
+                fifths += 7;
+                mode = rest;
+            } else if let Some(rest) = mode.strip_prefix('b') {
+                fifths -= 7;
+                mode = rest;
+            }
+
+            // The mode may also be written as a word of its own
+            if mode.is_empty()
+                && let Some(word) = tokens.next_if(|token| mode_fifths(token).is_some())
+            {
+                mode = word;
+            }
+
+            fifths += mode_fifths(mode).ok_or_else(unknown)?;
+
+            let count = usize::try_from(fifths.unsigned_abs()).map_err(|_| unknown())?;
+            if count > 7 {
+                return Err(unknown());
+            }
+
+            let (order, accidental) = if fifths > 0 {
+                (SHARP_ORDER, 1)
+            } else {
+                (FLAT_ORDER, -1)
+            };
+
+            for letter in &order[..count] {
+                key[*letter] = accidental;
+            }
+        }
+    }
+
+    // Explicit accidentals like ^f or _b change single letters of the key
+    for token in tokens {
+        let accidental = match token.chars().next() {
+            Some('^') => 1,
+            Some('_') => -1,
+            Some('=') => 0,
+
+            // Clefs, transpositions and the like
+            _ => continue,
+        };
+
+        let letter = token
+            .trim_start_matches(['^', '_', '='])
+            .chars()
+            .next()
+            .and_then(|letter| "CDEFGAB".find(letter.to_ascii_uppercase()))
+            .ok_or_else(unknown)?;
+
+        let amount = i32::try_from(token.chars().take_while(|c| *c == '^' || *c == '_').count())
+            .map_err(|_| unknown())?;
+        key[letter] = accidental * amount.max(1);
+    }
+
+    Ok(key)
+}
+
+/// How many fifths a mode lies below or above major, `None` for an unknown mode.
+fn mode_fifths(mode: &str) -> Option<i32> {
+    let mode = mode.to_ascii_lowercase();
+    if mode.is_empty() || mode == "m" {
+        return Some(if mode.is_empty() { 0 } else { -3 });
+    }
+
+    match mode.get(..3)? {
+        "maj" | "ion" => Some(0),
+        "min" | "aeo" => Some(-3),
+        "mix" => Some(-1),
+        "dor" => Some(-2),
+        "phr" => Some(-4),
+        "lyd" => Some(1),
+        "loc" => Some(-5),
+        _ => None,
+    }
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> Option<u64> {
+    let mut number: Option<u64> = None;
+
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = Some(
+            number
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(u64::from(digit)),
+        );
+    }
+
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first note of every chord of a tune in C, where rests count as 0.
+    fn notes(body: &str) -> Vec<u16> {
+        chords_from_abc(&format!("X:1\nK:C\n{body}\n"))
+            .unwrap()
+            .iter()
+            .map(|chord| chord.notes.first().copied().unwrap_or(0))
+            .collect()
+    }
+
+    #[test]
+    fn repeats_are_played_twice() {
+        assert_eq!(notes("|:CD:|E|"), [262, 294, 262, 294, 330]);
+        assert_eq!(notes("CD:|E"), [262, 294, 262, 294, 330]);
+        assert_eq!(notes("|:C::D:|"), [262, 262, 294, 294]);
+    }
+
+    #[test]
+    fn endings_are_taken_in_turn() {
+        assert_eq!(notes("|:C|1D:|2E|"), [262, 294, 262, 330]);
+        assert_eq!(notes("|:C[1D:|[2E|"), [262, 294, 262, 330]);
+    }
+
+    #[test]
+    fn broken_rhythm_moves_time_between_notes() {
+        let durations = |body: &str| {
+            chords_from_abc(&format!("X:1\nL:1/8\nK:C\n{body}\n"))
+                .unwrap()
+                .iter()
+                .map(|chord| chord.duration)
+                .collect::<Vec<_>>()
+        };
+
+        // An eighth note lasts 250 ms at the default tempo
+        let ms = Duration::from_millis;
+        assert_eq!(durations("C>D"), [ms(375), ms(125)]);
+        assert_eq!(durations("C<D"), [ms(125), ms(375)]);
+
+        let us = Duration::from_micros;
+        assert_eq!(durations("C>>D"), [us(437_500), us(62_500)]);
+    }
+
+    #[test]
+    fn keys_and_accidentals_change_notes() {
+        let key = |key: &str, body: &str| {
+            chords_from_abc(&format!("X:1\nK:{key}\n{body}\n"))
+                .unwrap()
+                .iter()
+                .map(|chord| chord.notes[0])
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(key("G", "F"), [370]);
+        assert_eq!(key("F", "B"), [466]);
+        assert_eq!(key("Ddor", "FB"), [349, 494]);
+        assert_eq!(key("D mixolydian", "FC"), [370, 262]);
+        assert_eq!(key("C ^f", "F"), [370]);
+
+        // Accidentals last until the end of the bar
+        assert_eq!(key("G", "=F F|F"), [349, 349, 370]);
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        let result = chords_from_abc("X:1\nK:Q\nC\n");
+        assert!(matches!(result, Err(Error::InvalidAbc(_))));
+    }
+
+    #[test]
+    fn overflowing_lengths_are_refused() {
+        let tunes = [
+            // The denominator doubles for every slash
+            format!("C{}", "/".repeat(70)),
+            format!("C{}D", ">".repeat(40)),
+            format!("C{}D", "<".repeat(4)),
+            "C99999999999999999999".to_string(),
+            "C/99999999999/99999999999".to_string(),
+            "(99999999999999999999 C".to_string(),
+            "Z99999999999999999999".to_string(),
+            "[M:18446744073709551615+1/4]C".to_string(),
+            "[L:1/18446744073709551615]C/99".to_string(),
+        ];
+
+        for tune in tunes {
+            let result = chords_from_abc(&format!("X:1\nK:C\n{tune}\n"));
+            assert!(
+                matches!(result, Err(Error::InvalidAbc(_))),
+                "{tune}: {result:?}"
+            );
+        }
+    }
+}
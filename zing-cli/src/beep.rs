@@ -0,0 +1,162 @@
+use crate::{Error, Result};
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The defaults of the `beep` utility for options that are left out.
+const DEFAULT_FREQUENCY: u16 = 440;
+const DEFAULT_LENGTH: u64 = 200;
+const DEFAULT_REPETITIONS: u32 = 1;
+const DEFAULT_DELAY: u64 = 100;
+
+/// A single `beep` specification, the options between two `-n` separators.
+struct Beep {
+    frequency: u16,
+    length: Duration,
+    repetitions: u32,
+    delay: Duration,
+
+    /// Whether the delay also follows the last repetition (`-D` rather than `-d`)
+    delay_after_last: bool,
+}
+
+impl Default for Beep {
+    fn default() -> Self {
+        Self {
+            frequency: DEFAULT_FREQUENCY,
+            length: Duration::from_millis(DEFAULT_LENGTH),
+            repetitions: DEFAULT_REPETITIONS,
+            delay: Duration::from_millis(DEFAULT_DELAY),
+            delay_after_last: false,
+        }
+    }
+}
+
+impl Beep {
+    fn push_chords(&self, chords: &mut Vec<Chord>) {
+        for repetition in 1..=self.repetitions {
+            let last = repetition == self.repetitions;
+
+            chords.push(Chord {
+                extended_duration: self.length,
+                gap: if last && !self.delay_after_last {
+                    Duration::ZERO
+                } else {
+                    self.delay
+                },
+                notes: vec![self.frequency],
+                ..Chord::default()
+            });
+        }
+    }
+}
+
+/// Translates the command line arguments of the classic `beep` utility into a sequence of
+/// single-tone `Chord`s, so scripts written for `beep` can play through the daemon.
+///
+/// - `-f FREQ` sets the frequency in Hz (440 by default), fractions are rounded.
+/// - `-l LEN` sets the length of the tone in milliseconds (200 by default).
+/// - `-r REPS` repeats the tone (once by default).
+/// - `-d DELAY` sets the silence between repetitions in milliseconds (100 by default),
+///   `-D DELAY` also keeps quiet after the last repetition.
+/// - `-n` (or `--new`) starts a new tone, with the defaults again.
+///
+/// Values may follow their option directly (`-f440`) or as the next argument. `-e DEVICE`,
+/// `--verbose` and `--debug` are accepted and ignored, since the daemon owns the speaker.
+///
+/// # Errors
+/// Returns an error if an option is unknown or unsupported (such as `-s` and `-c`, which
+/// beep along with stdin), or its value is missing or invalid.
+///
+/// # Example
+/// ```text
+/// -f 1000 -l 50 -r 3 -d 25 -n -f 2000 -l 100
+/// ```
+pub fn chords_from_beep_args<S: AsRef<str>>(args: &[S]) -> Result<Vec<Chord>> {
+    let mut chords = Vec::new();
+    let mut beep = Beep::default();
+    let mut args = args.iter().map(AsRef::as_ref);
+
+    while let Some(arg) = args.next() {
+        if matches!(arg, "-n" | "--new") {
+            beep.push_chords(&mut chords);
+            beep = Beep::default();
+            continue;
+        }
+
+        if matches!(arg, "--verbose" | "--debug") {
+            continue;
+        }
+
+        let Some(option) = arg
+            .strip_prefix('-')
+            .and_then(|option| option.chars().next())
+        else {
+            return Err(invalid(format!("unexpected argument '{arg}'")));
+        };
+
+        // The value either follows the option directly or is the next argument
+        let attached = &arg[1 + option.len_utf8()..];
+        let mut value = || {
+            if attached.is_empty() {
+                args.next()
+                    .ok_or_else(|| invalid(format!("-{option} needs a value")))
+            } else {
+                Ok(attached)
+            }
+        };
+
+        match option {
+            'f' => beep.frequency = frequency(value()?)?,
+            'l' => beep.length = milliseconds(option, value()?)?,
+            'r' => beep.repetitions = number(option, value()?)?,
+            'd' | 'D' => {
+                beep.delay = milliseconds(option, value()?)?;
+                beep.delay_after_last = option == 'D';
+            }
+            'e' => {
+                value()?;
+            }
+            's' | 'c' => {
+                return Err(invalid(format!(
+                    "-{option} (beeping along with stdin) is not supported"
+                )));
+            }
+            _ => return Err(invalid(format!("unknown option '{arg}'"))),
+        }
+    }
+
+    beep.push_chords(&mut chords);
+    Ok(chords)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidBeepArguments(message.into())
+}
+
+fn frequency(value: &str) -> Result<u16> {
+    let frequency: f64 = value
+        .parse()
+        .map_err(|_| invalid(format!("invalid frequency '{value}'")))?;
+
+    let rounded = frequency.round();
+    if !(1.0..=f64::from(u16::MAX)).contains(&rounded) {
+        return Err(invalid(format!(
+            "frequency {value} is out of range, expected 1 to {}",
+            u16::MAX
+        )));
+    }
+
+    // The rounded frequency is checked above, so it always fits
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Ok(rounded as u16)
+}
+
+fn milliseconds(option: char, value: &str) -> Result<Duration> {
+    Ok(Duration::from_millis(number(option, value)?))
+}
+
+fn number<T: std::str::FromStr>(option: char, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid value '{value}' for -{option}")))
+}
@@ -1,11 +1,26 @@
 use std::fmt;
+use zing_protocol::ErrorReason;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     ZingProtocol(zing_protocol::Error),
+    Daemon(ErrorReason),
+    UnexpectedResponse,
     Io(std::io::Error),
+    Wav(hound::Error),
+    Midi(midly::Error),
+    InvalidMidi(String),
+    UnsupportedMidiFormat,
+    UnsupportedExportFormat(crate::Format),
+    Xml(roxmltree::Error),
+    Zip(zip::result::ZipError),
+    InvalidMusicXml(String),
+    InvalidRtttl(String),
+    InvalidAbc(String),
+    InvalidMml(String),
+    InvalidBeepArguments(String),
     NoteDoesNotExist(char),
     OctaveDoesNotExist(usize),
     InvalidOctave(String),
@@ -17,7 +32,28 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::ZingProtocol(e) => write!(f, "Could not communicate with daemon: {e}"),
+            Error::Daemon(reason) => write!(f, "Daemon refused the request: {reason}"),
+            Error::UnexpectedResponse => write!(f, "Daemon sent an unexpected response"),
             Error::Io(e) => write!(f, "Io error: {e}"),
+            Error::Wav(e) => write!(f, "Could not write WAV file: {e}"),
+            Error::Midi(e) => write!(f, "Invalid MIDI file: {e}"),
+            Error::InvalidMidi(e) => write!(f, "Invalid MIDI file: {e}"),
+            Error::Xml(e) => write!(f, "Invalid MusicXML file: {e}"),
+            Error::Zip(e) => write!(f, "Invalid compressed MusicXML file: {e}"),
+            Error::InvalidMusicXml(e) => write!(f, "Invalid MusicXML: {e}"),
+            Error::InvalidRtttl(e) => write!(f, "Invalid RTTTL: {e}"),
+            Error::InvalidAbc(e) => write!(f, "Invalid ABC notation: {e}"),
+            Error::InvalidMml(e) => write!(f, "Invalid MML: {e}"),
+            Error::InvalidBeepArguments(e) => write!(f, "Invalid beep arguments: {e}"),
+            Error::UnsupportedMidiFormat => {
+                write!(
+                    f,
+                    "MIDI files with sequential tracks (format 2) are not supported"
+                )
+            }
+            Error::UnsupportedExportFormat(format) => {
+                write!(f, "Melodies cannot be exported to {format}, only to midi")
+            }
             Error::NoteDoesNotExist(c) => write!(f, "Note does dot exist: '{c}'"),
             Error::OctaveDoesNotExist(o) => write!(f, "Octave does not exist: {o}"),
             Error::InvalidOctave(e) => write!(f, "Invalid octave: {e}"),
@@ -0,0 +1,66 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file formats melodies can be read from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Notemap,
+    Midi,
+    Rtttl,
+    Abc,
+    Mml,
+    MusicXml,
+}
+
+impl Format {
+    /// Guesses the format of a file from its extension, falling back to notemap.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("mid" | "midi") => Format::Midi,
+            Some("rtttl" | "rtx") => Format::Rtttl,
+            Some("abc") => Format::Abc,
+            Some("mml" | "play") => Format::Mml,
+            Some("musicxml" | "mxl" | "xml") => Format::MusicXml,
+            _ => Format::Notemap,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Notemap => write!(f, "notemap"),
+            Format::Midi => write!(f, "midi"),
+            Format::Rtttl => write!(f, "rtttl"),
+            Format::Abc => write!(f, "abc"),
+            Format::Mml => write!(f, "mml"),
+            Format::MusicXml => write!(f, "musicxml"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "notemap" | "nm" => Ok(Format::Notemap),
+            "midi" | "mid" => Ok(Format::Midi),
+            "rtttl" | "rtx" => Ok(Format::Rtttl),
+            "abc" => Ok(Format::Abc),
+            "mml" | "play" => Ok(Format::Mml),
+            "musicxml" | "mxl" | "xml" => Ok(Format::MusicXml),
+            _ => Err(format!(
+                "unknown format '{s}', expected notemap, midi, rtttl, abc, mml or musicxml"
+            )),
+        }
+    }
+}
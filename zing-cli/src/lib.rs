@@ -2,8 +2,17 @@ use std::time::Duration;
 use zing_protocol::Chord;
 
 pub use error::{Error, Result};
+pub use format::Format;
 
+pub mod abc;
+pub mod beep;
 pub mod error;
+pub mod format;
+pub mod midi;
+pub mod mml;
+pub mod musicxml;
+pub mod render;
+pub mod rtttl;
 
 const OCTAVES: usize = 9;
 
@@ -20,6 +29,17 @@ const OCTAVES_F_SHARP: [u16; OCTAVES] = [23, 46, 92, 185, 369, 739, 1479, 2959,
 const OCTAVES_G: [u16; OCTAVES] = [24, 49, 98, 196, 392, 783, 1567, 3135, 6271];
 const OCTAVES_G_SHARP: [u16; OCTAVES] = [25, 51, 103, 207, 415, 830, 1661, 3322, 6644];
 
+/// Calculates the frequency of a MIDI note number in Hz, where note 69 is A4 at 440 Hz.
+#[must_use]
+pub fn midi_note_frequency(note: u8) -> u16 {
+    let frequency = 440.0 * 2.0_f64.powf((f64::from(note) - 69.0) / 12.0);
+
+    // The highest MIDI note is below 12.6 kHz, so this always fits
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let frequency = frequency.round() as u16;
+    frequency
+}
+
 /// Parses a notemap string into a compressed sequence of `Chord`s.
 ///
 /// A *notemap* is a textual representation of musical notes, where each line represents notes
@@ -39,7 +59,8 @@ const OCTAVES_G_SHARP: [u16; OCTAVES] = [25, 51, 103, 207, 415, 830, 1661, 3322,
 ///
 /// # Arguments
 /// * `notemap` - A string containing the musical notation in notemap format.
-/// * `chord_duration` - The base duration to assign to each chord segment.
+/// * `chord_duration` - The base duration to assign to each chord segment, which is
+///   also the time added to a chord for every dash that follows it.
 ///
 /// # Errors
 /// Returns an error if:
@@ -71,6 +92,7 @@ fn compress_chords(mut chords: Vec<Chord>, chord_duration: &Duration) -> Vec<Cho
         }
 
         chords[last_valid_index].extended_duration += racked_up_time;
+        chords[index].duration = *chord_duration;
         racked_up_time = Duration::ZERO;
         last_valid_index = index;
     }
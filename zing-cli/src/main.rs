@@ -1,9 +1,16 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use std::fs;
 use std::io::{self, Read};
-use zing_protocol::Command;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use zing_protocol::{
+    Capabilities, Chord, Command, Connection, Event, Hello, PlayData, Priority, QueueEntry,
+    Response, Status,
+};
 
-use zing::{Error, Result};
+use zing::midi::{MidiOptions, Reduction};
+use zing::musicxml::MusicXmlOptions;
+use zing::{Error, Format, Result, render};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -12,26 +19,65 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Play a file or read from stdin if no file is provided
+    #[command(flatten)]
+    input: Input,
+
+    /// How important the melody is (low, normal or high)
+    #[arg(short, long, default_value = "normal")]
+    priority: Priority,
+
+    /// The socket of the daemon, $ZING_SOCKET or /run/zingd.sock by default
+    #[arg(long, global = true, value_name = "PATH")]
+    socket: Option<PathBuf>,
+}
+
+// Where a melody is read from and how it is interpreted (not a doc comment, since clap
+// would turn it into the about text of every command it is flattened into)
+#[derive(Args)]
+struct Input {
+    /// The melody file, read from stdin if no file is provided
     #[arg(value_name = "FILE")]
-    file: Option<String>,
+    file: Option<PathBuf>,
 
-    /// The time per chord (e.g., "500ms", "2s", "1m")
+    /// The format of the melody (notemap, midi, rtttl, abc, mml or musicxml), guessed from the file extension by default
+    #[arg(short, long)]
+    format: Option<Format>,
+
+    /// The duration per chord (e.g., "500ms", "2s", "1m")
     #[arg(short, long, default_value = "100ms")]
     chord_duration: humantime::Duration,
+
+    /// The MIDI tracks to play, counting from 0 (e.g., "1,2"), all tracks by default
+    #[arg(long, value_delimiter = ',', value_name = "TRACKS")]
+    track: Vec<usize>,
+
+    /// The MIDI channels to play, counting from 1 (e.g., "1,10"), all channels by default
+    #[arg(long, value_delimiter = ',', value_name = "CHANNELS")]
+    channel: Vec<u8>,
+
+    /// How simultaneous MIDI notes become one tone (highest, lowest or arpeggiate)
+    #[arg(long, default_value = "highest")]
+    reduction: Reduction,
+
+    /// The MusicXML part to play, by id (e.g., "P1") or name, the first part by default
+    #[arg(long)]
+    part: Option<String>,
+
+    /// The MusicXML voice to play within the part, the voice of its first note by default
+    #[arg(long)]
+    voice: Option<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Plays a melody with the speaker, overrules any existing melodies
+    /// Plays a melody with the speaker, overrules any less important melodies
     Play {
-        /// Play a file or read from stdin if no file is provided
-        #[arg(value_name = "FILE")]
-        file: Option<String>,
+        #[command(flatten)]
+        input: Input,
 
-        /// The duration per chord (e.g., "500ms", "2s", "1m")
-        #[arg(short, long, default_value = "100ms")]
-        chord_duration: humantime::Duration,
+        /// How important the melody is (low, normal or high)
+        #[arg(short, long, default_value = "normal")]
+        priority: Priority,
     },
 
     /// Stop the currently playing melody
@@ -42,50 +88,448 @@ enum Commands {
 
     /// Resumes a paused melody
     Resume,
+
+    /// Shows what the speaker is currently playing
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Manages the melodies that play one after the other
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommands,
+    },
+
+    /// Renders a melody to a WAV file instead of playing it
+    Render {
+        #[command(flatten)]
+        input: Input,
+
+        /// The WAV file to write
+        #[arg(short, long, value_name = "OUTPUT")]
+        output: PathBuf,
+
+        /// The amount of samples per second
+        #[arg(long, default_value_t = render::DEFAULT_SAMPLE_RATE)]
+        sample_rate: u32,
+    },
+
+    /// Converts a melody to a file for other software, such as a Standard MIDI file
+    #[command(mut_arg("format", |arg| {
+        arg.long("from")
+            .short(None)
+            .help("The format of the melody to convert (notemap, midi, rtttl, abc, mml or musicxml), guessed from the file extension by default")
+    }))]
+    Export {
+        #[command(flatten)]
+        input: Input,
+
+        /// The file to write
+        #[arg(short, long, value_name = "OUTPUT")]
+        output: PathBuf,
+
+        /// The format to write (midi), guessed from the output extension, midi by default
+        #[arg(long = "format", value_name = "FORMAT")]
+        output_format: Option<Format>,
+    },
+
+    /// Plays tones given in the syntax of the classic beep command (e.g., "-f 1000 -l 50 -r 3")
+    Beep {
+        /// The beep options: -f FREQ, -l LEN, -r REPS, -d DELAY, -D DELAY and -n
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "ARGS"
+        )]
+        args: Vec<String>,
+    },
+
+    /// Shows the versions of zing and the daemon, and what the daemon can play
+    Info,
+
+    /// Prints everything the speaker does as it happens, until interrupted
+    Watch {
+        /// Print every event as a line of JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueueCommands {
+    /// Adds a melody to the back of the queue
+    Add {
+        #[command(flatten)]
+        input: Input,
+
+        /// How important the melody is (low, normal or high)
+        #[arg(short, long, default_value = "normal")]
+        priority: Priority,
+    },
+
+    /// Lists the melodies waiting in the queue
+    List,
+
+    /// Stops the current melody and plays the next one in the queue
+    Skip,
+
+    /// Removes all melodies from the queue
+    Clear,
 }
 
 fn run() -> Result<()> {
+    // Scripts written for beep keep working when zing is installed under its name
+    if invoked_as_beep() {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        return beep(&zing_protocol::socket_path(), &args);
+    }
+
     let cli = Cli::parse();
+    let socket = cli.socket.unwrap_or_else(zing_protocol::socket_path);
 
     let command = match cli.command {
         Some(cmd) => cmd,
         None => Commands::Play {
-            file: cli.file,
-            chord_duration: cli.chord_duration,
+            input: cli.input,
+            priority: cli.priority,
         },
     };
 
-    match command {
-        Commands::Play {
-            file,
-            chord_duration,
+    let command = match command {
+        Commands::Play { input, priority } => Command::Play(read_play_data(&input, priority)?),
+
+        Commands::Stop => Command::Stop,
+        Commands::Pause => Command::Pause,
+        Commands::Resume => Command::Resume,
+
+        Commands::Status { json } => {
+            let (mut connection, _) = connect(&socket)?;
+            let Response::Status(status) = request(&mut connection, &Command::Status)? else {
+                return Err(Error::UnexpectedResponse);
+            };
+
+            print_status(&status, json);
+            return Ok(());
+        }
+
+        Commands::Queue { command } => match command {
+            QueueCommands::Add { input, priority } => {
+                Command::Enqueue(read_play_data(&input, priority)?)
+            }
+
+            QueueCommands::List => {
+                let (mut connection, _) = connect(&socket)?;
+                let Response::Queue(queue) = request(&mut connection, &Command::ListQueue)? else {
+                    return Err(Error::UnexpectedResponse);
+                };
+
+                print_queue(&queue);
+                return Ok(());
+            }
+
+            QueueCommands::Skip => Command::Skip,
+            QueueCommands::Clear => Command::ClearQueue,
+        },
+
+        Commands::Render {
+            input,
+            output,
+            sample_rate,
+        } => {
+            let data = read_play_data(&input, Priority::default())?;
+            return render::render_wav(&data.chords, sample_rate, &output);
+        }
+
+        Commands::Export {
+            input,
+            output,
+            output_format,
         } => {
-            let notemap = if let Some(file) = file {
-                fs::read_to_string(file).map_err(Error::Io)?
-            } else {
-                let mut buffer = String::new();
-                io::stdin()
-                    .read_to_string(&mut buffer)
-                    .expect("Failed to read from stdin");
-                buffer
+            let data = read_play_data(&input, Priority::default())?;
+            let format = output_format.unwrap_or_else(|| match Format::from_path(&output) {
+                Format::Notemap => Format::Midi,
+                format => format,
+            });
+
+            let bytes = match format {
+                Format::Midi => zing::midi::midi_from_chords(&data.chords)?,
+                format => return Err(Error::UnsupportedExportFormat(format)),
+            };
+
+            return fs::write(&output, bytes).map_err(Error::Io);
+        }
+
+        Commands::Beep { args } => return beep(&socket, &args),
+
+        Commands::Info => {
+            let (_, capabilities) = connect(&socket)?;
+            print_info(&capabilities);
+            return Ok(());
+        }
+
+        Commands::Watch { json } => {
+            let (connection, _) = connect(&socket)?;
+            let events = connection.subscribe().map_err(Error::ZingProtocol)?;
+
+            for event in events {
+                print_event(&event.map_err(Error::ZingProtocol)?, json);
+            }
+
+            return Ok(());
+        }
+    };
+
+    let (mut connection, _) = connect(&socket)?;
+    request(&mut connection, &command)?;
+    Ok(())
+}
+
+/// Whether the binary was started through a file or link named `beep`.
+fn invoked_as_beep() -> bool {
+    std::env::args_os()
+        .next()
+        .map(PathBuf::from)
+        .and_then(|program| program.file_name().map(|name| name == "beep"))
+        .unwrap_or(false)
+}
+
+/// Plays the tones described by the arguments of the classic beep command.
+fn beep(socket: &Path, args: &[String]) -> Result<()> {
+    let chords = zing::beep::chords_from_beep_args(args)?;
+    let data = PlayData {
+        chords,
+        priority: Priority::default(),
+    };
+
+    let (mut connection, _) = connect(socket)?;
+    request(&mut connection, &Command::Play(data))?;
+    Ok(())
+}
+
+/// Reads a melody from a file, or from stdin if no file is provided.
+fn read_play_data(input: &Input, priority: Priority) -> Result<PlayData> {
+    let format = input
+        .format
+        .or_else(|| input.file.as_deref().map(Format::from_path))
+        .unwrap_or_default();
+
+    let bytes = read_input(input.file.as_deref())?;
+    let chord_duration = *input.chord_duration;
+
+    let chords = match format {
+        Format::Notemap => zing::chords_from_notemap(&into_text(bytes)?, &chord_duration)?,
+        Format::Rtttl => zing::rtttl::chords_from_rtttl(&into_text(bytes)?)?,
+        Format::Abc => zing::abc::chords_from_abc(&into_text(bytes)?)?,
+        Format::Mml => zing::mml::chords_from_mml(&into_text(bytes)?)?,
+
+        Format::Midi => {
+            let options = MidiOptions {
+                tracks: input.track.clone(),
+                channels: input.channel.clone(),
+                reduction: input.reduction,
+                chord_duration,
             };
 
-            let data = zing::chords_from_notemap(&notemap, &chord_duration)?;
+            zing::midi::chords_from_midi(&bytes, &options)?
+        }
 
-            let play_data = zing_protocol::PlayData {
-                chord_duration: *chord_duration,
-                chords: data,
+        Format::MusicXml => {
+            let options = MusicXmlOptions {
+                part: input.part.clone(),
+                voice: input.voice.clone(),
             };
 
-            zing_protocol::send(&Command::Play(play_data)).map_err(Error::ZingProtocol)?;
+            zing::musicxml::chords_from_musicxml(&bytes, &options)?
         }
+    };
+
+    Ok(PlayData { chords, priority })
+}
+
+fn into_text(bytes: Vec<u8>) -> Result<String> {
+    String::from_utf8(bytes).map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
 
-        Commands::Stop => zing_protocol::send(&Command::Stop).map_err(Error::ZingProtocol)?,
-        Commands::Pause => zing_protocol::send(&Command::Pause).map_err(Error::ZingProtocol)?,
-        Commands::Resume => zing_protocol::send(&Command::Resume).map_err(Error::ZingProtocol)?,
+fn read_input(file: Option<&Path>) -> Result<Vec<u8>> {
+    if let Some(file) = file {
+        return fs::read(file).map_err(Error::Io);
     }
 
-    Ok(())
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer).map_err(Error::Io)?;
+    Ok(buffer)
+}
+
+fn print_queue(queue: &[QueueEntry]) {
+    if queue.is_empty() {
+        println!("The queue is empty");
+        return;
+    }
+
+    for (index, entry) in queue.iter().enumerate() {
+        println!(
+            "{}. {} chords, {}, {} priority, queued by {}",
+            index + 1,
+            entry.chords,
+            format_duration(entry.duration),
+            entry.priority,
+            entry.client
+        );
+    }
+}
+
+fn print_status(status: &Status, json: bool) {
+    if json {
+        let value = serde_json::json!({
+            "state": status.state.to_string(),
+            "position": status.position,
+            "total": status.total,
+            "elapsed": status.elapsed.as_secs_f64(),
+            "remaining": status.remaining.as_secs_f64(),
+            "priority": status.priority.to_string(),
+            "client": status.client.map(|client| serde_json::json!({
+                "pid": client.pid,
+                "uid": client.uid,
+                "gid": client.gid,
+            })),
+        });
+
+        println!("{value}");
+        return;
+    }
+
+    println!("State: {}", status.state);
+
+    if let Some(client) = status.client {
+        println!("Chord: {}/{}", status.position + 1, status.total);
+        println!("Elapsed: {}", format_duration(status.elapsed));
+        println!("Remaining: {}", format_duration(status.remaining));
+        println!("Priority: {}", status.priority);
+        println!("Started by: {client}");
+    }
+}
+
+fn print_info(capabilities: &Capabilities) {
+    println!(
+        "Client: zing {}, protocol versions {} to {}",
+        env!("CARGO_PKG_VERSION"),
+        zing_protocol::MIN_PROTOCOL_VERSION,
+        zing_protocol::PROTOCOL_VERSION
+    );
+    println!(
+        "Daemon: {}, protocol versions {} to {}",
+        capabilities.agent, capabilities.min_version, capabilities.max_version
+    );
+    println!(
+        "Frequencies: {} Hz to {} Hz",
+        capabilities.min_frequency, capabilities.max_frequency
+    );
+}
+
+fn print_event(event: &Event, json: bool) {
+    if json {
+        let value = match event {
+            Event::MelodyStarted {
+                client,
+                priority,
+                chords,
+                duration,
+            } => serde_json::json!({
+                "event": "melody_started",
+                "client": {
+                    "pid": client.pid,
+                    "uid": client.uid,
+                    "gid": client.gid,
+                },
+                "priority": priority.to_string(),
+                "chords": chords,
+                "duration": duration.as_secs_f64(),
+            }),
+            Event::ChordAdvanced { position, chord } => serde_json::json!({
+                "event": "chord_advanced",
+                "position": position,
+                "notes": chord.notes,
+                "duration": chord.length().as_secs_f64(),
+            }),
+            Event::Paused => serde_json::json!({ "event": "paused" }),
+            Event::Resumed => serde_json::json!({ "event": "resumed" }),
+            Event::Finished => serde_json::json!({ "event": "finished" }),
+            Event::Stopped => serde_json::json!({ "event": "stopped" }),
+            Event::Preempted { priority } => serde_json::json!({
+                "event": "preempted",
+                "priority": priority.to_string(),
+            }),
+        };
+
+        println!("{value}");
+        return;
+    }
+
+    match event {
+        Event::MelodyStarted {
+            client,
+            priority,
+            chords,
+            duration,
+        } => println!(
+            "Started {chords} chords, {}, {priority} priority, played by {client}",
+            format_duration(*duration)
+        ),
+        Event::ChordAdvanced { position, chord } => {
+            println!("Chord {}: {}", position + 1, describe_chord(chord));
+        }
+        Event::Paused => println!("Paused"),
+        Event::Resumed => println!("Resumed"),
+        Event::Finished => println!("Finished"),
+        Event::Stopped => println!("Stopped"),
+        Event::Preempted { priority } => {
+            println!("Interrupted by a melody with {priority} priority");
+        }
+    }
+}
+
+fn describe_chord(chord: &Chord) -> String {
+    let notes = if chord.notes.is_empty() {
+        "rest".to_string()
+    } else {
+        chord
+            .notes
+            .iter()
+            .map(|note| format!("{note} Hz"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!("{notes} ({})", format_duration(chord.length()))
+}
+
+/// Formats a duration for humans, rounded to milliseconds.
+fn format_duration(duration: Duration) -> humantime::FormattedDuration {
+    let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    humantime::format_duration(Duration::from_millis(millis))
+}
+
+/// Connects to the daemon on `socket` and introduces zing, so a daemon that does not speak
+/// a protocol version in common refuses it before the first command is sent.
+fn connect(socket: &Path) -> Result<(Connection, Capabilities)> {
+    let mut connection = Connection::connect_to(socket).map_err(Error::ZingProtocol)?;
+    let hello = Hello::new(format!("zing {}", env!("CARGO_PKG_VERSION")));
+
+    let Response::Hello(capabilities) = request(&mut connection, &Command::Hello(hello))? else {
+        return Err(Error::UnexpectedResponse);
+    };
+
+    Ok((connection, capabilities))
+}
+
+/// Sends a command over `connection`, turning an error response into an `Error`.
+fn request(connection: &mut Connection, command: &Command) -> Result<Response> {
+    match connection.request(command).map_err(Error::ZingProtocol)? {
+        Response::Error(reason) => Err(Error::Daemon(reason)),
+        response => Ok(response),
+    }
 }
 
 fn main() {
@@ -0,0 +1,528 @@
+use crate::{Error, Result, midi_note_frequency};
+use midly::num::{u4, u7, u15, u24, u28};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The tempo of a MIDI file without tempo events, in microseconds per quarter note (120 BPM).
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// The resolution of exported files, in ticks per quarter note.
+const EXPORT_TICKS_PER_BEAT: u16 = 480;
+
+/// The velocity of exported notes, a little louder than the middle.
+const EXPORT_VELOCITY: u8 = 100;
+
+/// The shortest note an arpeggio is split into, to keep it from spinning forever.
+const MIN_ARPEGGIO_NOTE: Duration = Duration::from_millis(1);
+
+/// How simultaneous notes are reduced to the single tone a buzzer can play.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    /// Play only the highest note, which usually carries the melody
+    #[default]
+    Highest,
+
+    /// Play only the lowest note, which usually carries the bass line
+    Lowest,
+
+    /// Rapidly cycle through all notes
+    Arpeggiate,
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reduction::Highest => write!(f, "highest"),
+            Reduction::Lowest => write!(f, "lowest"),
+            Reduction::Arpeggiate => write!(f, "arpeggiate"),
+        }
+    }
+}
+
+impl FromStr for Reduction {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "highest" => Ok(Reduction::Highest),
+            "lowest" => Ok(Reduction::Lowest),
+            "arpeggiate" => Ok(Reduction::Arpeggiate),
+            _ => Err(format!(
+                "unknown reduction '{s}', expected highest, lowest or arpeggiate"
+            )),
+        }
+    }
+}
+
+/// Selects what part of a MIDI file is converted, and how.
+#[derive(Debug, Default, Clone)]
+pub struct MidiOptions {
+    /// The tracks to play, counting from 0, or every track if empty
+    pub tracks: Vec<usize>,
+
+    /// The channels to play, counting from 1, or every channel if empty
+    pub channels: Vec<u8>,
+
+    /// How simultaneous notes are reduced to a single tone
+    pub reduction: Reduction,
+
+    /// How long it takes to cycle through all notes of an arpeggiated chord
+    pub chord_duration: Duration,
+}
+
+/// An event that affects the conversion, at an absolute tick.
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    Tempo(u32),
+    NoteOn(u8),
+    NoteOff(u8),
+}
+
+/// A stretch of time during which the same notes sound.
+struct Segment {
+    start: Duration,
+    end: Duration,
+    notes: BTreeSet<u8>,
+
+    /// The notes that were struck at the start of the segment
+    struck: BTreeSet<u8>,
+}
+
+/// Parses a Standard MIDI File (format 0 or 1) into a sequence of single-tone `Chord`s.
+///
+/// Note on and note off events of the selected tracks and channels are turned into notes,
+/// timed by the ticks per quarter note of the file and its tempo events. Since the buzzer
+/// can only play one tone at a time, simultaneous notes are reduced according to
+/// `options.reduction`. Silences become chords without notes.
+///
+/// # Errors
+/// Returns an error if:
+/// - The file is not a valid Standard MIDI File, or divides time into 0 ticks.
+/// - The file plays its tracks one after the other (format 2).
+pub fn chords_from_midi(bytes: &[u8], options: &MidiOptions) -> Result<Vec<Chord>> {
+    let smf = Smf::parse(bytes).map_err(Error::Midi)?;
+
+    if smf.header.format == Format::Sequential {
+        return Err(Error::UnsupportedMidiFormat);
+    }
+
+    let zero_division = match smf.header.timing {
+        Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int() == 0,
+        Timing::Timecode(_, subframes) => subframes == 0,
+    };
+    if zero_division {
+        return Err(Error::InvalidMidi(
+            "the header divides time into 0 ticks".to_string(),
+        ));
+    }
+
+    let events = collect_events(&smf, options);
+    let segments = get_segments(&events, smf.header.timing)?;
+    Ok(reduce_segments(&segments, options))
+}
+
+/// Gathers the events of all tracks, ordered by their absolute tick.
+///
+/// Tempo events are taken from every track, since they affect the timing of all of them.
+fn collect_events(smf: &Smf, options: &MidiOptions) -> Vec<(u64, Event)> {
+    let mut events: Vec<(u64, Event)> = Vec::new();
+
+    for (index, track) in smf.tracks.iter().enumerate() {
+        let selected = options.tracks.is_empty() || options.tracks.contains(&index);
+        let mut tick: u64 = 0;
+
+        for event in track {
+            tick += u64::from(event.delta.as_int());
+
+            let event = match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => Event::Tempo(tempo.as_int()),
+                TrackEventKind::Midi { channel, message } if selected => {
+                    let channel = channel.as_int() + 1;
+                    if !options.channels.is_empty() && !options.channels.contains(&channel) {
+                        continue;
+                    }
+
+                    match message {
+                        MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            Event::NoteOn(key.as_int())
+                        }
+                        MidiMessage::NoteOn { key, .. } | MidiMessage::NoteOff { key, .. } => {
+                            Event::NoteOff(key.as_int())
+                        }
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            events.push((tick, event));
+        }
+    }
+
+    // A stable sort keeps the order of events that happen at the same tick
+    events.sort_by_key(|(tick, _)| *tick);
+    events
+}
+
+/// Splits the events into segments, each starting where the sounding notes change.
+fn get_segments(events: &[(u64, Event)], timing: Timing) -> Result<Vec<Segment>> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut active: BTreeSet<u8> = BTreeSet::new();
+    let mut struck: BTreeSet<u8> = BTreeSet::new();
+
+    let mut tempo = DEFAULT_TEMPO;
+    let mut seconds: f64 = 0.0;
+    let mut last_tick: u64 = 0;
+    let mut last_time = Duration::ZERO;
+
+    for group in events.chunk_by(|(a, _), (b, _)| a == b) {
+        let tick = group[0].0;
+
+        #[allow(clippy::cast_precision_loss)]
+        let ticks = (tick - last_tick) as f64;
+        seconds += ticks * seconds_per_tick(timing, tempo);
+        last_tick = tick;
+
+        let time = Duration::try_from_secs_f64(seconds)
+            .map_err(|e| Error::InvalidMidi(format!("the melody is too long: {e}")))?;
+        if time > last_time {
+            segments.push(Segment {
+                start: last_time,
+                end: time,
+                notes: active.clone(),
+                struck: std::mem::take(&mut struck),
+            });
+            last_time = time;
+        }
+
+        for (_, event) in group {
+            match *event {
+                Event::Tempo(new_tempo) => tempo = new_tempo,
+                Event::NoteOn(key) => {
+                    active.insert(key);
+                    struck.insert(key);
+                }
+                Event::NoteOff(key) => {
+                    active.remove(&key);
+                }
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+fn seconds_per_tick(timing: Timing, tempo: u32) -> f64 {
+    match timing {
+        Timing::Metrical(ticks_per_beat) => {
+            f64::from(tempo) / 1_000_000.0 / f64::from(ticks_per_beat.as_int())
+        }
+        Timing::Timecode(fps, subframes) => 1.0 / (f64::from(fps.as_f32()) * f64::from(subframes)),
+    }
+}
+
+/// Turns the segments into single-tone chords, joining notes that are held across segments.
+fn reduce_segments(segments: &[Segment], options: &MidiOptions) -> Vec<Chord> {
+    let mut chords: Vec<Chord> = Vec::new();
+
+    for segment in segments {
+        let duration = segment.end - segment.start;
+
+        // Rests before the first note are not worth waiting for
+        if segment.notes.is_empty() {
+            if !chords.is_empty() {
+                push_tone(&mut chords, None, duration, false);
+            }
+            continue;
+        }
+
+        let key = match options.reduction {
+            Reduction::Lowest => segment.notes.first(),
+            Reduction::Arpeggiate if segment.notes.len() > 1 => {
+                arpeggiate(
+                    &mut chords,
+                    &segment.notes,
+                    duration,
+                    options.chord_duration,
+                );
+                continue;
+            }
+            Reduction::Highest | Reduction::Arpeggiate => segment.notes.last(),
+        };
+
+        if let Some(key) = key {
+            let struck = segment.struck.contains(key);
+            push_tone(
+                &mut chords,
+                Some(midi_note_frequency(*key)),
+                duration,
+                struck,
+            );
+        }
+    }
+
+    // Trailing rests only delay the end of the melody
+    while chords.last().is_some_and(|chord| chord.notes.is_empty()) {
+        chords.pop();
+    }
+
+    chords
+}
+
+/// Cycles through the notes, spending `chord_duration` on every full cycle.
+fn arpeggiate(
+    chords: &mut Vec<Chord>,
+    notes: &BTreeSet<u8>,
+    duration: Duration,
+    chord_duration: Duration,
+) {
+    let step =
+        (chord_duration / u32::try_from(notes.len()).unwrap_or(u32::MAX)).max(MIN_ARPEGGIO_NOTE);
+    let mut remaining = duration;
+
+    for key in notes.iter().cycle() {
+        if remaining.is_zero() {
+            break;
+        }
+
+        let length = step.min(remaining);
+        push_tone(chords, Some(midi_note_frequency(*key)), length, true);
+        remaining -= length;
+    }
+}
+
+/// Adds a tone or a rest, lengthening the previous chord instead if it is held longer.
+fn push_tone(chords: &mut Vec<Chord>, frequency: Option<u16>, duration: Duration, struck: bool) {
+    let notes: Vec<u16> = frequency.into_iter().collect();
+
+    if let Some(last) = chords.last_mut()
+        && !struck
+        && last.notes == notes
+    {
+        last.duration += duration;
+        return;
+    }
+
+    chords.push(Chord {
+        duration,
+        notes,
+        ..Chord::default()
+    });
+}
+
+/// Writes chords to a Standard MIDI File (format 0), so they can be edited elsewhere.
+///
+/// Every frequency is mapped to the nearest MIDI note. The notes of a chord sound together
+/// for the duration of the chord, including its extended duration, followed by its gap.
+/// Chords without notes are rests. The file is timed at 120 beats per minute with 480 ticks
+/// per quarter note, which keeps durations accurate to about a millisecond.
+///
+/// # Errors
+/// Returns an error if the file cannot be written.
+pub fn midi_from_chords(chords: &[Chord]) -> Result<Vec<u8>> {
+    let mut track: Vec<TrackEvent> = vec![TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(DEFAULT_TEMPO))),
+    }];
+
+    // Times are kept absolute, so rounding to ticks does not add up over a long melody
+    let mut time = Duration::ZERO;
+    let mut last_tick: u64 = 0;
+    let mut push = |tick: u64, kind: TrackEventKind<'static>| {
+        let delta = u32::try_from(tick - last_tick).unwrap_or(u32::MAX);
+        track.push(TrackEvent {
+            delta: u28::new(delta.min(u28::max_value().as_int())),
+            kind,
+        });
+        last_tick = tick;
+    };
+
+    for chord in chords {
+        let start = time_to_tick(time);
+        time += chord.duration + chord.extended_duration;
+        let end = time_to_tick(time);
+        time += chord.gap;
+
+        let keys: BTreeSet<u8> = chord
+            .notes
+            .iter()
+            .map(|note| frequency_note(*note))
+            .collect();
+
+        for key in &keys {
+            push(start, note_message(*key, EXPORT_VELOCITY));
+        }
+        for key in &keys {
+            push(end, note_message(*key, 0));
+        }
+    }
+
+    // Rests at the end would be lost without an event after them
+    push(
+        time_to_tick(time),
+        TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    );
+
+    let header = Header::new(
+        Format::SingleTrack,
+        Timing::Metrical(u15::new(EXPORT_TICKS_PER_BEAT)),
+    );
+    let mut smf = Smf::new(header);
+    smf.tracks.push(track);
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).map_err(Error::Io)?;
+    Ok(bytes)
+}
+
+/// Converts a time from the start to ticks at the tempo of exported files.
+fn time_to_tick(time: Duration) -> u64 {
+    let ticks_per_second =
+        u128::from(EXPORT_TICKS_PER_BEAT) * 1_000_000 / u128::from(DEFAULT_TEMPO);
+    let ticks = (time.as_nanos() * ticks_per_second + 500_000_000) / 1_000_000_000;
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+/// A note on event, or a note off event when `velocity` is 0.
+fn note_message(key: u8, velocity: u8) -> TrackEventKind<'static> {
+    let key = u7::new(key);
+    let message = if velocity == 0 {
+        MidiMessage::NoteOff {
+            key,
+            vel: u7::new(0),
+        }
+    } else {
+        MidiMessage::NoteOn {
+            key,
+            vel: u7::new(velocity),
+        }
+    };
+
+    TrackEventKind::Midi {
+        channel: u4::new(0),
+        message,
+    }
+}
+
+/// Finds the MIDI note closest to a frequency in Hz, the inverse of `midi_note_frequency`.
+fn frequency_note(frequency: u16) -> u8 {
+    let note = 69.0 + 12.0 * (f64::from(frequency.max(1)) / 440.0).log2();
+
+    // The note is clamped to the MIDI range, so it always fits
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let note = note.round().clamp(0.0, 127.0) as u8;
+    note
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A format 0 file with the given division that plays middle C for 96 ticks.
+    fn single_note(division: u16) -> Vec<u8> {
+        let track = [
+            0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+        ];
+
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&division.to_be_bytes());
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&u32::try_from(track.len()).unwrap().to_be_bytes());
+        bytes.extend_from_slice(&track);
+        bytes
+    }
+
+    #[test]
+    fn notes_are_timed_by_the_division() {
+        let chords = chords_from_midi(&single_note(96), &MidiOptions::default()).unwrap();
+
+        assert_eq!(chords.len(), 1);
+        assert_eq!(chords[0].notes, [262]);
+        assert_eq!(chords[0].duration, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn a_division_of_zero_is_refused() {
+        let metrical = chords_from_midi(&single_note(0), &MidiOptions::default());
+        assert!(matches!(metrical, Err(Error::InvalidMidi(_))));
+
+        // 25 frames per second without subframes
+        let timecode = chords_from_midi(&single_note(0xe700), &MidiOptions::default());
+        assert!(matches!(timecode, Err(Error::InvalidMidi(_))));
+    }
+
+    #[test]
+    fn frequencies_map_to_the_nearest_note() {
+        assert_eq!(frequency_note(440), 69);
+        assert_eq!(frequency_note(262), 60);
+        assert_eq!(frequency_note(450), 69);
+        assert_eq!(frequency_note(0), 0);
+        assert_eq!(frequency_note(u16::MAX), 127);
+    }
+
+    #[test]
+    fn exported_files_are_single_tracks() {
+        let bytes = midi_from_chords(&[]).unwrap();
+        let smf = Smf::parse(&bytes).unwrap();
+
+        assert_eq!(smf.header.format, Format::SingleTrack);
+        assert_eq!(smf.header.timing, Timing::Metrical(u15::new(480)));
+        assert_eq!(smf.tracks.len(), 1);
+    }
+
+    #[test]
+    fn exported_chords_import_again() {
+        let ms = Duration::from_millis;
+        let chords = [
+            Chord {
+                duration: ms(500),
+                gap: ms(250),
+                notes: vec![440],
+                ..Chord::default()
+            },
+            Chord {
+                duration: ms(250),
+                ..Chord::default()
+            },
+            Chord {
+                duration: ms(500),
+                extended_duration: ms(250),
+                notes: vec![523, 659],
+                ..Chord::default()
+            },
+            Chord {
+                duration: ms(500),
+                notes: vec![659],
+                ..Chord::default()
+            },
+        ];
+
+        let bytes = midi_from_chords(&chords).unwrap();
+        let imported = chords_from_midi(&bytes, &MidiOptions::default()).unwrap();
+        let imported: Vec<(Vec<u16>, Duration)> = imported
+            .into_iter()
+            .map(|chord| {
+                (
+                    chord.notes,
+                    chord.duration + chord.extended_duration + chord.gap,
+                )
+            })
+            .collect();
+
+        // The gap and the rest after it become a single rest, the chord its highest note
+        assert_eq!(
+            imported,
+            [
+                (vec![440], ms(500)),
+                (Vec::new(), ms(500)),
+                (vec![659], ms(750)),
+                (vec![659], ms(500)),
+            ]
+        );
+    }
+}
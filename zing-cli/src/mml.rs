@@ -0,0 +1,290 @@
+use crate::{Error, Result, midi_note_frequency};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The defaults of a QBasic `PLAY` string that does not set its own.
+const DEFAULT_TEMPO: u32 = 120;
+const DEFAULT_LENGTH: u32 = 4;
+const DEFAULT_OCTAVE: u32 = 4;
+
+const MAX_OCTAVE: u32 = 6;
+const MAX_NOTE_NUMBER: u32 = 84;
+
+/// How much of its length a note sounds, the rest is silent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Articulation {
+    /// The note sounds for its whole length (`ML`)
+    Legato,
+
+    /// The note sounds for 7/8 of its length (`MN`)
+    Normal,
+
+    /// The note sounds for 3/4 of its length (`MS`)
+    Staccato,
+}
+
+impl Articulation {
+    /// The part of a note's length that sounds, as a fraction.
+    fn fraction(self) -> (u32, u32) {
+        match self {
+            Articulation::Legato => (1, 1),
+            Articulation::Normal => (7, 8),
+            Articulation::Staccato => (3, 4),
+        }
+    }
+}
+
+/// The settings that apply to every note until they are changed.
+struct State {
+    tempo: u32,
+    length: u32,
+
+    /// Dots that come with the default length (`L4.`)
+    length_dots: u32,
+
+    octave: u32,
+    articulation: Articulation,
+}
+
+/// Parses a Music Macro Language string, as played by QBasic's `PLAY`, into a sequence
+/// of single-tone `Chord`s.
+///
+/// Commands are single letters, optionally followed by a number. Letters may be written in
+/// either case, spaces and semicolons between commands are ignored.
+///
+/// - `A`–`G` play a note, followed by `#` or `+` for sharp and `-` for flat, an optional
+///   length and optional dots. Every dot makes the note half as long again.
+/// - `N` plays a note by number (1–84, where 0 is a rest), `P` and `R` rest.
+/// - `L` sets the length of notes without their own (1 is a whole note, 4 a quarter note),
+///   `T` the tempo in quarter notes per minute (32–255).
+/// - `O` sets the octave (0–6, where `O4 C` is middle C), `>` and `<` move one octave up
+///   or down.
+/// - `ML`, `MN` and `MS` play notes legato, normal or staccato. `MF` and `MB` are accepted
+///   and ignored.
+///
+/// The part of a note that does not sound becomes the gap of its chord.
+///
+/// # Errors
+/// Returns an error if a command is unknown, or its number is missing or out of range.
+///
+/// # Example
+/// ```text
+/// T120 L8 O4 CDEFG > C
+/// ```
+pub fn chords_from_mml(mml: &str) -> Result<Vec<Chord>> {
+    let mut state = State {
+        tempo: DEFAULT_TEMPO,
+        length: DEFAULT_LENGTH,
+        length_dots: 0,
+        octave: DEFAULT_OCTAVE,
+        articulation: Articulation::Normal,
+    };
+
+    let mut chords = Vec::new();
+    let mut chars = mml.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c.to_ascii_uppercase() {
+            'A'..='G' => {
+                let semitone = note_semitone(c.to_ascii_uppercase());
+                let accidental = match chars.next_if(|c| matches!(c, '#' | '+' | '-')) {
+                    Some('-') => -1,
+                    Some(_) => 1,
+                    None => 0,
+                };
+
+                let (length, dots) = note_length(&mut chars, &state)?;
+                let note = i64::from(state.octave + 1) * 12 + semitone + accidental;
+                let note =
+                    u8::try_from(note).map_err(|_| invalid(format!("{c} is out of range")))?;
+                chords.push(chord(Some(note), length, dots, &state));
+            }
+            'N' => {
+                let number = number(&mut chars, 'N', 0..=MAX_NOTE_NUMBER)?;
+                let dots = take_dots(&mut chars);
+
+                // N1 is the lowest C, N0 a rest
+                let note = (number > 0)
+                    .then(|| u8::try_from(number + 11))
+                    .transpose()
+                    .map_err(|_| invalid(format!("N{number} is out of range")))?;
+                chords.push(chord(note, state.length, state.length_dots + dots, &state));
+            }
+            'P' | 'R' => {
+                let length = number(&mut chars, c, 1..=64)?;
+                let dots = take_dots(&mut chars);
+                chords.push(chord(None, length, dots, &state));
+            }
+            'L' => {
+                state.length = number(&mut chars, 'L', 1..=64)?;
+                state.length_dots = take_dots(&mut chars);
+            }
+            'T' => state.tempo = number(&mut chars, 'T', 32..=255)?,
+            'O' => state.octave = number(&mut chars, 'O', 0..=MAX_OCTAVE)?,
+            '>' => state.octave = (state.octave + 1).min(MAX_OCTAVE),
+            '<' => state.octave = state.octave.saturating_sub(1),
+            'M' => match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('L') => state.articulation = Articulation::Legato,
+                Some('N') => state.articulation = Articulation::Normal,
+                Some('S') => state.articulation = Articulation::Staccato,
+
+                // Playing in the foreground or background makes no difference to the daemon
+                Some('F' | 'B') => {}
+                _ => return Err(invalid("M must be followed by L, N, S, F or B")),
+            },
+            'X' | '=' => return Err(invalid("substrings and variables are not supported")),
+            c if c.is_whitespace() || c == ';' => {}
+            c => return Err(invalid(format!("unknown command '{c}'"))),
+        }
+    }
+
+    Ok(chords)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidMml(message.into())
+}
+
+fn note_semitone(letter: char) -> i64 {
+    match letter {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        _ => 11,
+    }
+}
+
+/// Reads the optional length and dots of a note, falling back to the default length.
+fn note_length(chars: &mut Peekable<Chars>, state: &State) -> Result<(u32, u32)> {
+    match take_number(chars) {
+        Some(length) if (1..=64).contains(&length) => Ok((length, take_dots(chars))),
+        Some(length) => Err(invalid(format!("invalid note length {length}"))),
+        None => Ok((state.length, state.length_dots + take_dots(chars))),
+    }
+}
+
+/// Creates the chord for a note (or a rest without note) of `1/length` whole note.
+fn chord(note: Option<u8>, length: u32, dots: u32, state: &State) -> Chord {
+    // A whole note lasts four beats
+    let mut total = Duration::from_secs(240) / state.tempo / length;
+
+    let mut dot = total;
+    for _ in 0..dots {
+        dot /= 2;
+        total += dot;
+    }
+
+    let Some(note) = note else {
+        return Chord {
+            duration: total,
+            ..Chord::default()
+        };
+    };
+
+    let (numerator, denominator) = state.articulation.fraction();
+    let duration = total * numerator / denominator;
+
+    Chord {
+        duration,
+        gap: total - duration,
+        notes: vec![midi_note_frequency(note)],
+        ..Chord::default()
+    }
+}
+
+/// Reads the number of a command, which must lie within `range`.
+fn number(
+    chars: &mut Peekable<Chars>,
+    command: char,
+    range: std::ops::RangeInclusive<u32>,
+) -> Result<u32> {
+    let command = command.to_ascii_uppercase();
+    let number = take_number(chars).ok_or_else(|| invalid(format!("{command} needs a number")))?;
+
+    if range.contains(&number) {
+        Ok(number)
+    } else {
+        Err(invalid(format!(
+            "{command}{number} is out of range, expected {} to {}",
+            range.start(),
+            range.end()
+        )))
+    }
+}
+
+fn take_dots(chars: &mut Peekable<Chars>) -> u32 {
+    let mut dots = 0;
+    while chars.next_if_eq(&'.').is_some() {
+        dots += 1;
+    }
+
+    dots
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let mut number: Option<u32> = None;
+
+    // QBasic allows spaces between a command and its number
+    while chars.next_if(|c| *c == ' ').is_some() {}
+
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = Some(number.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(mml: &str) -> Vec<u16> {
+        chords_from_mml(mml)
+            .unwrap()
+            .iter()
+            .map(|chord| chord.notes.first().copied().unwrap_or(0))
+            .collect()
+    }
+
+    #[test]
+    fn notes_follow_the_octave() {
+        assert_eq!(notes("CDE"), [262, 294, 330]);
+        assert_eq!(notes("c# d- e+"), [277, 277, 349]);
+        assert_eq!(notes("O2 A > A < < A"), [110, 220, 55]);
+        assert_eq!(notes("O6 > C"), [1047]);
+        assert_eq!(notes("N49 N0 N1 O0 C"), [262, 0, 16, 16]);
+    }
+
+    #[test]
+    fn lengths_follow_tempo_articulation_and_dots() {
+        let timing = |mml: &str| {
+            let chord = chords_from_mml(mml).unwrap().remove(0);
+            (chord.duration.as_millis(), chord.gap.as_millis())
+        };
+
+        // A quarter note lasts half a second at the default tempo
+        assert_eq!(timing("ML C"), (500, 0));
+        assert_eq!(timing("MS C"), (375, 125));
+        assert_eq!(timing("T60 ML C8"), (500, 0));
+        assert_eq!(timing("ML L2. C"), (1500, 0));
+        assert_eq!(timing("ML C4.."), (875, 0));
+        assert_eq!(timing("P4"), (500, 0));
+    }
+
+    #[test]
+    fn invalid_commands_are_refused() {
+        for mml in ["T10", "O7", "N85", "L0", "C65", "P", "MX", "X", "H"] {
+            let result = chords_from_mml(mml);
+            assert!(
+                matches!(result, Err(Error::InvalidMml(_))),
+                "{mml}: {result:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,590 @@
+use crate::{Error, Result, midi_note_frequency};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+use std::time::Duration;
+use zing_protocol::Chord;
+use zip::ZipArchive;
+
+/// The tempo used until a score sets its own, in quarter notes per minute.
+const DEFAULT_TEMPO: f64 = 120.0;
+
+/// The voice of notes that do not name one.
+const DEFAULT_VOICE: &str = "1";
+
+/// The file in a compressed MusicXML archive that points to the score.
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Selects what part of a MusicXML score is converted.
+#[derive(Debug, Default, Clone)]
+pub struct MusicXmlOptions {
+    /// The part to play, by id (e.g., "P1") or name, or the first part if `None`
+    pub part: Option<String>,
+
+    /// The voice to play within the part, or the voice of its first note if `None`
+    pub voice: Option<String>,
+}
+
+/// A note, chord or rest of the selected voice, timed in seconds from the start.
+struct Event {
+    start: f64,
+    length: f64,
+    notes: Vec<u16>,
+
+    /// Whether the event continues the previous one through a tie
+    tied: bool,
+}
+
+/// Where the conversion is within the part.
+struct State {
+    /// The divisions of a quarter note, which note durations are counted in
+    divisions: f64,
+
+    /// Quarter notes per minute
+    tempo: f64,
+
+    /// The time of the next note, in seconds
+    cursor: f64,
+
+    /// The start of the last note, which notes marked as chord share
+    last_start: f64,
+}
+
+impl State {
+    /// How many seconds `duration` divisions take at the current tempo.
+    fn seconds(&self, duration: f64) -> f64 {
+        duration / self.divisions * 60.0 / self.tempo
+    }
+}
+
+/// Parses a MusicXML score (uncompressed `.musicxml` or compressed `.mxl`) into a sequence
+/// of `Chord`s.
+///
+/// A single voice of a single part is played, selected by `options`. Note durations are
+/// counted in the divisions of the part, timed by the tempo of `<sound tempo="...">`
+/// (or a metronome marking without one). Notes marked as `<chord/>` are played together,
+/// tied notes are held and rests become chords without notes. Grace notes are skipped and
+/// repeats are played once.
+///
+/// # Errors
+/// Returns an error if:
+/// - The file is not valid XML, or not a valid MusicXML archive.
+/// - The score is not a partwise score.
+/// - The selected part or voice does not exist.
+/// - The divisions of a part are not positive, or the melody is too long.
+pub fn chords_from_musicxml(bytes: &[u8], options: &MusicXmlOptions) -> Result<Vec<Chord>> {
+    let text = read_score(bytes)?;
+    let parsing = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+
+    let document = Document::parse_with_options(&text, parsing).map_err(Error::Xml)?;
+    let score = document.root_element();
+    if !score.has_tag_name("score-partwise") {
+        return Err(invalid(format!(
+            "expected a partwise score, found <{}>",
+            score.tag_name().name()
+        )));
+    }
+
+    let part = find_part(score, options.part.as_deref())?;
+    let voice = match &options.voice {
+        Some(voice) => voice.clone(),
+        None => first_voice(part),
+    };
+
+    let tempos = other_tempos(score, part);
+    let events = collect_events(part, &voice, &tempos)?;
+    if events.is_empty() {
+        return Err(invalid(format!("voice {voice} has no notes")));
+    }
+
+    into_chords(&events)
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::InvalidMusicXml(message.into())
+}
+
+/// Reads the score from a MusicXML file, unpacking it first if it is compressed.
+fn read_score(bytes: &[u8]) -> Result<String> {
+    // Compressed MusicXML files are zip archives
+    if !bytes.starts_with(b"PK\x03\x04") {
+        return into_text(bytes.to_vec());
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(Error::Zip)?;
+    let path = match read_entry(&mut archive, CONTAINER_PATH) {
+        Ok(container) => root_file(&container)?,
+
+        // The container is required, but the score is easy to find without it
+        Err(_) => archive
+            .file_names()
+            .find(|name| {
+                !name.starts_with("META-INF/")
+                    && (name.ends_with(".musicxml") || name.ends_with(".xml"))
+            })
+            .map(str::to_string)
+            .ok_or_else(|| invalid("the archive does not contain a score"))?,
+    };
+
+    read_entry(&mut archive, &path)
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, path: &str) -> Result<String> {
+    let mut entry = archive.by_name(path).map_err(Error::Zip)?;
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(Error::Io)?;
+    into_text(bytes)
+}
+
+fn into_text(bytes: Vec<u8>) -> Result<String> {
+    let text = String::from_utf8(bytes)
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+
+    // Notation software likes to start its files with a byte order mark
+    Ok(text
+        .strip_prefix('\u{feff}')
+        .map(str::to_string)
+        .unwrap_or(text))
+}
+
+/// Finds the path of the score in the `container.xml` of a compressed file.
+fn root_file(container: &str) -> Result<String> {
+    let document = Document::parse(container).map_err(Error::Xml)?;
+    document
+        .descendants()
+        .find(|node| node.has_tag_name("rootfile"))
+        .and_then(|node| node.attribute("full-path"))
+        .map(str::to_string)
+        .ok_or_else(|| invalid("the container does not name a score"))
+}
+
+/// Finds the part with the given id or name, or the first part.
+fn find_part<'a>(score: Node<'a, 'a>, selected: Option<&str>) -> Result<Node<'a, 'a>> {
+    let parts: Vec<Node> = children(score, "part").collect();
+
+    let Some(selected) = selected else {
+        return parts
+            .first()
+            .copied()
+            .ok_or_else(|| invalid("the score has no parts"));
+    };
+
+    // Parts are named in the part list, and refer to it by id
+    let names: Vec<(&str, String)> = children(score, "part-list")
+        .flat_map(|list| children(list, "score-part"))
+        .filter_map(|part| {
+            let name = child_text(part, "part-name").unwrap_or_default();
+            Some((part.attribute("id")?, name.to_string()))
+        })
+        .collect();
+
+    let id = names
+        .iter()
+        .find(|(id, name)| *id == selected || name.eq_ignore_ascii_case(selected))
+        .map_or(selected, |(id, _)| *id);
+
+    parts
+        .into_iter()
+        .find(|part| part.attribute("id") == Some(id))
+        .ok_or_else(|| {
+            let available: Vec<String> = names
+                .iter()
+                .map(|(id, name)| format!("{id} ({name})"))
+                .collect();
+            invalid(format!(
+                "unknown part '{selected}', expected one of {}",
+                available.join(", ")
+            ))
+        })
+}
+
+/// The voice of the first note in the part.
+fn first_voice(part: Node) -> String {
+    part.descendants()
+        .find(|node| node.has_tag_name("note"))
+        .map_or(DEFAULT_VOICE, voice)
+        .to_string()
+}
+
+fn voice<'a>(note: Node<'a, '_>) -> &'a str {
+    child_text(note, "voice").unwrap_or(DEFAULT_VOICE)
+}
+
+/// Finds the tempo markings of the other parts, which apply to the whole score, by the
+/// index of their measure.
+fn other_tempos(score: Node, part: Node) -> HashMap<usize, f64> {
+    let mut tempos = HashMap::new();
+
+    for other in children(score, "part").filter(|other| *other != part) {
+        for (index, measure) in children(other, "measure").enumerate() {
+            let tempo = measure
+                .children()
+                .rev()
+                .filter(|element| {
+                    element.has_tag_name("direction") || element.has_tag_name("sound")
+                })
+                .find_map(tempo);
+
+            if let Some(tempo) = tempo {
+                tempos.insert(index, tempo);
+            }
+        }
+    }
+
+    tempos
+}
+
+/// Times the notes of the part, keeping the notes and rests of `selected_voice`.
+///
+/// The tempo markings of other parts take effect at the start of their measure, the
+/// markings of the part itself where they appear.
+fn collect_events(
+    part: Node,
+    selected_voice: &str,
+    other_tempos: &HashMap<usize, f64>,
+) -> Result<Vec<Event>> {
+    let mut events: Vec<Event> = Vec::new();
+    let mut state = State {
+        divisions: 1.0,
+        tempo: DEFAULT_TEMPO,
+        cursor: 0.0,
+        last_start: 0.0,
+    };
+
+    for (index, measure) in children(part, "measure").enumerate() {
+        if let Some(tempo) = other_tempos.get(&index) {
+            state.tempo = *tempo;
+        }
+
+        for element in measure.children() {
+            collect_element(&mut events, &mut state, element, selected_voice)?;
+        }
+    }
+
+    Ok(events)
+}
+
+/// Applies an element of a measure to the state, adding notes of the selected voice.
+fn collect_element(
+    events: &mut Vec<Event>,
+    state: &mut State,
+    element: Node,
+    selected_voice: &str,
+) -> Result<()> {
+    match element.tag_name().name() {
+        "attributes" => {
+            if let Some(divisions) = child_number(element, "divisions") {
+                if !(divisions.is_finite() && divisions > 0.0) {
+                    return Err(invalid(format!(
+                        "a quarter note cannot be divided into {divisions}"
+                    )));
+                }
+
+                state.divisions = divisions;
+            }
+        }
+        "direction" | "sound" => {
+            if let Some(tempo) = tempo(element) {
+                state.tempo = tempo;
+            }
+        }
+        "backup" => {
+            let duration = child_number(element, "duration").unwrap_or(0.0);
+            state.cursor = (state.cursor - state.seconds(duration)).max(0.0);
+        }
+        "forward" => {
+            let duration = child_number(element, "duration").unwrap_or(0.0);
+            state.cursor += state.seconds(duration);
+        }
+        "note" => push_note(events, state, element, selected_voice),
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Reads the tempo of a direction, preferring the tempo that should be played over the
+/// metronome marking that is printed.
+fn tempo(element: Node) -> Option<f64> {
+    let sound = element
+        .descendants()
+        .filter(|node| node.has_tag_name("sound"))
+        .find_map(|sound| sound.attribute("tempo")?.trim().parse().ok());
+
+    sound
+        .or_else(|| {
+            let metronome = element
+                .descendants()
+                .find(|node| node.has_tag_name("metronome"))?;
+
+            let per_minute: f64 = child_number(metronome, "per-minute")?;
+            let quarters = match child_text(metronome, "beat-unit")? {
+                "whole" => 4.0,
+                "half" => 2.0,
+                "quarter" => 1.0,
+                "eighth" => 0.5,
+                "16th" => 0.25,
+                _ => return None,
+            };
+
+            let dotted = children(metronome, "beat-unit-dot").count() > 0;
+            let quarters = if dotted { quarters * 1.5 } else { quarters };
+            Some(per_minute * quarters)
+        })
+        .filter(|tempo| *tempo > 0.0)
+}
+
+/// Times a note, adding it to the events if it belongs to the selected voice.
+fn push_note(events: &mut Vec<Event>, state: &mut State, note: Node, selected_voice: &str) {
+    // Grace notes take no time of their own
+    if has_child(note, "grace") {
+        return;
+    }
+
+    let chord = has_child(note, "chord");
+    let length = state.seconds(child_number(note, "duration").unwrap_or(0.0));
+    let start = if chord {
+        state.last_start
+    } else {
+        state.cursor
+    };
+
+    if !chord {
+        state.last_start = start;
+        state.cursor = start + length;
+    }
+
+    if voice(note) != selected_voice {
+        return;
+    }
+
+    // Cue notes are printed, but not played
+    let frequency = if has_child(note, "cue") {
+        None
+    } else {
+        pitch(note)
+    };
+
+    if chord && let Some(previous) = events.last_mut() {
+        previous.notes.extend(frequency);
+        return;
+    }
+
+    let tied = children(note, "tie").any(|tie| tie.attribute("type") == Some("stop"));
+    events.push(Event {
+        start,
+        length,
+        notes: frequency.into_iter().collect(),
+        tied,
+    });
+}
+
+/// The frequency of a pitched note, `None` for rests and unpitched percussion.
+fn pitch(note: Node) -> Option<u16> {
+    let pitch = children(note, "pitch").next()?;
+    let semitone = match child_text(pitch, "step")? {
+        "C" => 0.0,
+        "D" => 2.0,
+        "E" => 4.0,
+        "F" => 5.0,
+        "G" => 7.0,
+        "A" => 9.0,
+        "B" => 11.0,
+        _ => return None,
+    };
+
+    let octave = child_number(pitch, "octave")?;
+    let alter = child_number(pitch, "alter").unwrap_or(0.0);
+    let key = ((octave + 1.0) * 12.0 + semitone + alter).round();
+
+    // The key is checked, so it always fits
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    (0.0..=127.0)
+        .contains(&key)
+        .then(|| midi_note_frequency(key as u8))
+}
+
+/// Turns the events into chords, filling silences between them and joining tied notes.
+fn into_chords(events: &[Event]) -> Result<Vec<Chord>> {
+    let mut chords: Vec<Chord> = Vec::new();
+    let mut end = 0.0;
+
+    for event in events {
+        // Time skipped with <forward> is silent
+        if event.start - end > f64::EPSILON {
+            push_chord(&mut chords, Vec::new(), event.start - end, false)?;
+        }
+
+        let mut notes = event.notes.clone();
+        notes.sort_unstable();
+        notes.dedup();
+
+        push_chord(&mut chords, notes, event.length, event.tied)?;
+        end = event.start + event.length;
+    }
+
+    // Trailing rests only delay the end of the melody
+    while chords.last().is_some_and(|chord| chord.notes.is_empty()) {
+        chords.pop();
+    }
+
+    Ok(chords)
+}
+
+/// Adds a chord, holding the previous chord instead if it is tied to it.
+fn push_chord(chords: &mut Vec<Chord>, notes: Vec<u16>, seconds: f64, tied: bool) -> Result<()> {
+    let too_long = |e| invalid(format!("the melody is too long: {e}"));
+    let duration = Duration::try_from_secs_f64(seconds.max(0.0)).map_err(too_long)?;
+
+    if let Some(previous) = chords.last_mut()
+        && (tied || notes.is_empty())
+        && previous.notes == notes
+    {
+        previous.extended_duration = previous
+            .extended_duration
+            .checked_add(duration)
+            .ok_or_else(|| invalid("the melody is too long"))?;
+        return Ok(());
+    }
+
+    chords.push(Chord {
+        duration,
+        notes,
+        ..Chord::default()
+    });
+    Ok(())
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name(name))
+}
+
+fn has_child(node: Node, name: &'static str) -> bool {
+    children(node, name).next().is_some()
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &'static str) -> Option<&'a str> {
+    children(node, name).next()?.text().map(str::trim)
+}
+
+fn child_number(node: Node, name: &'static str) -> Option<f64> {
+    child_text(node, name)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A score with a flute part of two voices and a bass part of a single note, with the
+    /// given divisions, duration of every note and tempo.
+    fn score(divisions: &str, duration: &str, tempo: &str) -> String {
+        let note = |step: &str, octave: u8, voice: u8| {
+            format!(
+                "<note><pitch><step>{step}</step><octave>{octave}</octave></pitch>\
+                 <duration>{duration}</duration><voice>{voice}</voice></note>"
+            )
+        };
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <score-partwise version="4.0">
+              <part-list>
+                <score-part id="P1"><part-name>Flute</part-name></score-part>
+                <score-part id="P2"><part-name>Bass</part-name></score-part>
+              </part-list>
+              <part id="P1">
+                <measure number="1">
+                  <attributes><divisions>{divisions}</divisions></attributes>
+                  <direction><sound tempo="{tempo}"/></direction>
+                  {a4}<backup><duration>{duration}</duration></backup>{c4}
+                </measure>
+              </part>
+              <part id="P2">
+                <measure number="1">
+                  <attributes><divisions>{divisions}</divisions></attributes>
+                  {e2}
+                </measure>
+              </part>
+            </score-partwise>"#,
+            a4 = note("A", 4, 1),
+            c4 = note("C", 4, 2),
+            e2 = note("E", 2, 1),
+        )
+    }
+
+    fn notes(options: &MusicXmlOptions) -> Vec<u16> {
+        chords_from_musicxml(score("1", "1", "60").as_bytes(), options)
+            .unwrap()
+            .iter()
+            .flat_map(|chord| chord.notes.clone())
+            .collect()
+    }
+
+    #[test]
+    fn the_first_voice_of_the_first_part_is_played() {
+        let chords = chords_from_musicxml(
+            score("2", "2", "60").as_bytes(),
+            &MusicXmlOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(chords.len(), 1);
+        assert_eq!(chords[0].notes, [440]);
+        assert_eq!(chords[0].duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn parts_and_voices_are_selected() {
+        let select = |part: Option<&str>, voice: Option<&str>| MusicXmlOptions {
+            part: part.map(str::to_string),
+            voice: voice.map(str::to_string),
+        };
+
+        assert_eq!(notes(&select(None, Some("2"))), [262]);
+        assert_eq!(notes(&select(Some("P2"), None)), [82]);
+        assert_eq!(notes(&select(Some("bass"), None)), [82]);
+        assert_eq!(notes(&select(Some("Flute"), Some("2"))), [262]);
+
+        let unknown_part =
+            chords_from_musicxml(score("1", "1", "60").as_bytes(), &select(Some("P3"), None));
+        assert!(matches!(unknown_part, Err(Error::InvalidMusicXml(_))));
+
+        let unknown_voice =
+            chords_from_musicxml(score("1", "1", "60").as_bytes(), &select(None, Some("3")));
+        assert!(matches!(unknown_voice, Err(Error::InvalidMusicXml(_))));
+    }
+
+    #[test]
+    fn malformed_timing_is_refused() {
+        let scores = [
+            score("0", "1", "60"),
+            score("-1", "1", "60"),
+            score("1", "1e300", "60"),
+            score("1", "1", "1e-300"),
+        ];
+
+        for score in scores {
+            let result = chords_from_musicxml(score.as_bytes(), &MusicXmlOptions::default());
+            assert!(
+                matches!(result, Err(Error::InvalidMusicXml(_))),
+                "{result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_documents_are_refused() {
+        let timewise = chords_from_musicxml(b"<score-timewise/>", &MusicXmlOptions::default());
+        assert!(matches!(timewise, Err(Error::InvalidMusicXml(_))));
+
+        let broken = chords_from_musicxml(b"<score-partwise>", &MusicXmlOptions::default());
+        assert!(matches!(broken, Err(Error::Xml(_))));
+    }
+}
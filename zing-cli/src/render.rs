@@ -0,0 +1,109 @@
+use crate::{Error, Result};
+use std::path::Path;
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The sample rate used when no other rate is requested.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// The loudness of the square wave, a quarter of the full range to leave some headroom.
+const AMPLITUDE: i16 = i16::MAX / 4;
+
+/// Synthesizes chords as a square wave, timed the way the daemon plays them.
+///
+/// Every note of a chord gets an equal share of the chord duration, after which the
+/// last note is held for the extended duration of the chord, followed by the silent gap.
+/// Chords without notes are silent for their entire length.
+///
+/// # Arguments
+/// * `chords` - The chords to render, as produced by `chords_from_notemap`.
+/// * `sample_rate` - The amount of samples per second.
+#[must_use]
+pub fn render_samples(chords: &[Chord], sample_rate: u32) -> Vec<i16> {
+    let mut synth = Synth::new(sample_rate);
+
+    for chord in chords {
+        let Some(last) = chord.notes.last() else {
+            synth.tone(0, chord.length());
+            continue;
+        };
+
+        // The daemon divides the chord duration over the notes the same way
+        let note_duration = chord.duration / u32::try_from(chord.notes.len()).unwrap_or(u32::MAX);
+
+        for note in &chord.notes {
+            synth.tone(*note, note_duration);
+        }
+
+        synth.tone(*last, chord.extended_duration);
+        synth.tone(0, chord.gap);
+    }
+
+    synth.samples
+}
+
+/// Renders chords to a mono 16-bit WAV file.
+///
+/// # Errors
+/// Returns an error if the file cannot be created or written.
+pub fn render_wav(chords: &[Chord], sample_rate: u32, path: &Path) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec).map_err(Error::Wav)?;
+    for sample in render_samples(chords, sample_rate) {
+        writer.write_sample(sample).map_err(Error::Wav)?;
+    }
+
+    writer.finalize().map_err(Error::Wav)
+}
+
+/// Generates a continuous square wave, one tone after the other.
+struct Synth {
+    sample_rate: u32,
+    samples: Vec<i16>,
+
+    /// The time covered by all tones so far, kept exact to avoid rounding drift
+    elapsed: Duration,
+
+    /// The position within the current wave cycle, from 0 to 1
+    phase: f64,
+}
+
+impl Synth {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+            elapsed: Duration::ZERO,
+            phase: 0.0,
+        }
+    }
+
+    /// Appends a tone of the given frequency, where a frequency of 0 is silence.
+    fn tone(&mut self, frequency: u16, duration: Duration) {
+        self.elapsed += duration;
+
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let end = (self.elapsed.as_secs_f64() * f64::from(self.sample_rate)).round() as usize;
+        let step = f64::from(frequency) / f64::from(self.sample_rate);
+
+        while self.samples.len() < end {
+            if frequency == 0 {
+                self.samples.push(0);
+                continue;
+            }
+
+            self.samples.push(if self.phase < 0.5 {
+                AMPLITUDE
+            } else {
+                -AMPLITUDE
+            });
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{Error, Result, midi_note_frequency};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+use zing_protocol::Chord;
+
+/// The defaults the RTTTL specification prescribes when the header leaves them out.
+const DEFAULT_DURATION: u32 = 4;
+const DEFAULT_OCTAVE: u32 = 6;
+const DEFAULT_BPM: u32 = 63;
+
+/// The settings from the header, applied to notes that do not specify their own.
+struct Defaults {
+    duration: u32,
+    octave: u32,
+    bpm: u32,
+}
+
+/// Parses an RTTTL (Nokia ring tone) string into a sequence of single-tone `Chord`s.
+///
+/// An RTTTL string consists of three parts separated by colons: the name of the tune,
+/// the defaults and the notes.
+///
+/// - The defaults set the duration (`d`), octave (`o`) and beats per minute (`b`).
+/// - Every note is written as `[duration]note[#][.][octave][.]`, where the note is one
+///   of `a`–`g` (`h` is accepted as `b`) or `p` for a pause.
+/// - The duration is a fraction of a whole note (1, 2, 4, 8, 16 or 32), a dot makes the
+///   note one and a half times as long.
+///
+/// Every note becomes a chord with a single note, pauses become chords without notes.
+///
+/// # Errors
+/// Returns an error if the string does not consist of three parts, or if a default or
+/// note cannot be parsed.
+///
+/// # Example
+/// ```text
+/// Beethoven:d=4,o=5,b=160:c,e,g,8c6,p,2g.
+/// ```
+pub fn chords_from_rtttl(rtttl: &str) -> Result<Vec<Chord>> {
+    let mut parts = rtttl.trim().splitn(3, ':');
+    let (Some(_name), Some(defaults), Some(notes)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::InvalidRtttl(
+            "expected a name, defaults and notes separated by ':'".to_string(),
+        ));
+    };
+
+    let defaults = get_defaults(defaults)?;
+
+    notes
+        .split(',')
+        .map(str::trim)
+        .filter(|note| !note.is_empty())
+        .map(|note| get_chord(note, &defaults))
+        .collect()
+}
+
+fn get_defaults(section: &str) -> Result<Defaults> {
+    let mut defaults = Defaults {
+        duration: DEFAULT_DURATION,
+        octave: DEFAULT_OCTAVE,
+        bpm: DEFAULT_BPM,
+    };
+
+    for setting in section.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((key, value)) = setting.split_once('=') else {
+            return Err(Error::InvalidRtttl(format!("invalid default '{setting}'")));
+        };
+
+        let value: u32 = value
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidRtttl(format!("invalid default '{setting}'")))?;
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "d" => defaults.duration = value,
+            "o" => defaults.octave = value,
+            "b" => defaults.bpm = value,
+            _ => return Err(Error::InvalidRtttl(format!("unknown default '{setting}'"))),
+        }
+    }
+
+    if defaults.duration == 0 || defaults.bpm == 0 {
+        return Err(Error::InvalidRtttl(
+            "the duration and tempo must be above 0".to_string(),
+        ));
+    }
+
+    Ok(defaults)
+}
+
+fn get_chord(note: &str, defaults: &Defaults) -> Result<Chord> {
+    let invalid = || Error::InvalidRtttl(format!("invalid note '{note}'"));
+    let mut chars = note.chars().peekable();
+
+    let duration = take_number(&mut chars).unwrap_or(defaults.duration);
+    if duration == 0 {
+        return Err(invalid());
+    }
+
+    let semitone = match chars.next().map(|c| c.to_ascii_lowercase()) {
+        Some('c') => Some(0),
+        Some('d') => Some(2),
+        Some('e') => Some(4),
+        Some('f') => Some(5),
+        Some('g') => Some(7),
+        Some('a') => Some(9),
+        Some('b' | 'h') => Some(11),
+        Some('p') => None,
+        _ => return Err(invalid()),
+    };
+
+    let sharp = chars.next_if_eq(&'#').is_some();
+    let mut dotted = chars.next_if_eq(&'.').is_some();
+    let octave = take_number(&mut chars).unwrap_or(defaults.octave);
+    dotted |= chars.next_if_eq(&'.').is_some();
+
+    if chars.next().is_some() {
+        return Err(invalid());
+    }
+
+    // A whole note lasts four beats
+    let mut length = Duration::from_secs(240) / defaults.bpm / duration;
+    if dotted {
+        length = length * 3 / 2;
+    }
+
+    let notes = match semitone {
+        Some(semitone) => {
+            let midi_note = octave
+                .checked_add(1)
+                .and_then(|octave| octave.checked_mul(12))
+                .and_then(|note| note.checked_add(semitone + u32::from(sharp)))
+                .and_then(|note| u8::try_from(note).ok())
+                .filter(|note| *note < 128)
+                .ok_or_else(invalid)?;
+            vec![midi_note_frequency(midi_note)]
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Chord {
+        duration: length,
+        notes,
+        ..Chord::default()
+    })
+}
+
+fn take_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let mut number: Option<u32> = None;
+
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = Some(number.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+
+    number
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(rtttl: &str) -> Vec<u16> {
+        chords_from_rtttl(rtttl)
+            .unwrap()
+            .iter()
+            .map(|chord| chord.notes.first().copied().unwrap_or(0))
+            .collect()
+    }
+
+    fn durations(rtttl: &str) -> Vec<u128> {
+        chords_from_rtttl(rtttl)
+            .unwrap()
+            .iter()
+            .map(|chord| chord.duration.as_millis())
+            .collect()
+    }
+
+    #[test]
+    fn defaults_apply_to_notes_without_their_own() {
+        // A quarter note in octave 6 at 63 beats per minute, unless the header says otherwise
+        assert_eq!(notes("x::a"), [1760]);
+        assert_eq!(durations("x::a"), [952]);
+
+        assert_eq!(notes("x:d=8,o=5,b=120:a,a4,2a"), [880, 440, 880]);
+        assert_eq!(durations("x:d=8,o=5,b=120:a,a4,2a"), [250, 250, 1000]);
+        assert_eq!(durations("x: D = 4 , B = 60 :a"), [1000]);
+    }
+
+    #[test]
+    fn dots_lengthen_notes_in_either_position() {
+        assert_eq!(
+            durations("x:d=4,o=5,b=60:a.,a.5,a5.,a"),
+            [1500, 1500, 1500, 1000]
+        );
+        assert_eq!(notes("x:d=4,o=5,b=60:a.,a.6,a6."), [880, 1760, 1760]);
+    }
+
+    #[test]
+    fn sharps_and_h_change_the_note() {
+        assert_eq!(
+            notes("x:d=4,o=4,b=60:c,c#,h,b,H#"),
+            [262, 277, 494, 494, 523]
+        );
+    }
+
+    #[test]
+    fn pauses_are_silent() {
+        let chords = chords_from_rtttl("x:d=4,o=5,b=60:a,8p,p.").unwrap();
+
+        assert!(chords[1].notes.is_empty());
+        assert!(chords[2].notes.is_empty());
+        assert_eq!(chords[1].duration, Duration::from_millis(500));
+        assert_eq!(chords[2].duration, Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn invalid_tunes_are_refused() {
+        let tunes = [
+            "x:d=4,o=5,b=60:0a",
+            "x:d=0:a",
+            "x:b=0:a",
+            "x:d=x:a",
+            "x:d4:a",
+            "x:q=4:a",
+            "x:a",
+            "x",
+            "x::i",
+            "x::a#5x",
+            "x:o=4294967295,b=100:c",
+            "x::c4294967295",
+            "x:o=10:g#",
+        ];
+
+        for tune in tunes {
+            let result = chords_from_rtttl(tune);
+            assert!(
+                matches!(result, Err(Error::InvalidRtttl(_))),
+                "{tune}: {result:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::frame::{MAX_FRAME_LENGTH, read_frame, write_frame};
+use crate::{Command, Error, Event, Response, Result};
+use std::io::ErrorKind;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// A connection to the daemon that can carry any number of requests.
+///
+/// Every request is answered before the next one is sent, so responses always belong
+/// to the request that was sent last.
+pub struct Connection {
+    stream: UnixStream,
+}
+
+impl Connection {
+    /// Connects to the daemon listening on the socket named by `ZING_SOCKET`, or else on
+    /// `SOCKET_PATH`.
+    ///
+    /// # Errors
+    /// Returns an error if the socket does not exist or cannot be connected to.
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(crate::socket_path())
+    }
+
+    /// Connects to a daemon listening on another socket.
+    ///
+    /// # Errors
+    /// Returns an error if the socket does not exist or cannot be connected to.
+    pub fn connect_to(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    /// Uses an already connected stream.
+    #[must_use]
+    pub fn from_stream(stream: UnixStream) -> Self {
+        Self { stream }
+    }
+
+    /// Sends a command and waits for the `Response` of the daemon.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The command cannot be serialized or the response cannot be deserialized.
+    /// - Reading from or writing to the socket fails.
+    /// - The daemon closed the connection without answering.
+    pub fn request(&mut self, command: &Command) -> Result<Response> {
+        match self.send(command) {
+            Ok(()) => (),
+
+            // The daemon may have answered and hung up without reading, when it refused us
+            Err(Error::Io(e))
+                if matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset) =>
+            {
+                return self.receive().ok().flatten().ok_or(Error::Io(e));
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.receive()?.ok_or(Error::ConnectionClosed)
+    }
+
+    /// Subscribes to the events of the daemon, the connection is used for nothing else.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The daemon refused the subscription.
+    /// - Reading from or writing to the socket fails.
+    pub fn subscribe(mut self) -> Result<Events> {
+        match self.request(&Command::Subscribe)? {
+            Response::Ok => Ok(Events { connection: self }),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+
+    /// Sends a command without waiting for the response.
+    ///
+    /// The response is still sent by the daemon, it has to be read before the next one.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The command cannot be serialized.
+    /// - Writing to the socket fails.
+    pub fn send(&mut self, command: &Command) -> Result<()> {
+        write_frame(&mut self.stream, &command.as_bytes()?)
+    }
+
+    /// Reads the next response, returns `None` if the daemon closed the connection.
+    fn receive(&mut self) -> Result<Option<Response>> {
+        read_frame(&mut self.stream, MAX_FRAME_LENGTH)?
+            .map(|message| Response::from_bytes(&message))
+            .transpose()
+    }
+}
+
+/// The events sent by the daemon after a subscription, ends when the daemon disconnects.
+pub struct Events {
+    connection: Connection,
+}
+
+impl Iterator for Events {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.connection.receive() {
+            Ok(Some(Response::Event(event))) => Some(Ok(event)),
+            Ok(Some(_)) => Some(Err(Error::UnexpectedResponse)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
@@ -12,4 +12,28 @@ pub enum Error {
 
     #[from]
     Io(std::io::Error),
+
+    /// The message does not start with the magic bytes, it was sent by an older peer.
+    #[display("The message has no protocol header, the other side is too old")]
+    MissingHeader,
+
+    /// The message was written with a protocol version this build does not understand.
+    #[display(
+        "Incompatible protocol version {_0}, only versions {} to {} are understood",
+        crate::MIN_PROTOCOL_VERSION,
+        crate::PROTOCOL_VERSION
+    )]
+    IncompatibleVersion(#[error(not(source))] u16),
+
+    /// A frame announced a message longer than `frame::MAX_FRAME_LENGTH`.
+    #[display("The message of {_0} bytes is too large")]
+    FrameTooLarge(#[error(not(source))] usize),
+
+    /// The other side closed the connection before answering.
+    #[display("The connection was closed before an answer was received")]
+    ConnectionClosed,
+
+    /// The other side answered with a response that does not fit the request.
+    #[display("An unexpected response was received")]
+    UnexpectedResponse,
 }
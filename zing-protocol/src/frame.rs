@@ -0,0 +1,55 @@
+use crate::{Error, Result};
+use std::io::{ErrorKind, Read, Write};
+
+/// The largest message that can be sent in a single frame.
+pub const MAX_FRAME_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// Writes a message prefixed with its length, so many messages can share one stream.
+///
+/// # Errors
+/// Returns an error if:
+/// - The message is longer than `MAX_FRAME_LENGTH`.
+/// - Writing to the stream fails.
+pub fn write_frame(writer: &mut impl Write, message: &[u8]) -> Result<()> {
+    let length = u32::try_from(message.len())
+        .ok()
+        .filter(|length| *length <= MAX_FRAME_LENGTH)
+        .ok_or(Error::FrameTooLarge(message.len()))?;
+
+    let mut frame = Vec::with_capacity(message.len() + size_of::<u32>());
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(message);
+
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads the next message written by `write_frame`, refusing messages over `max_length`.
+///
+/// Returns `None` when the stream ends between two frames.
+///
+/// # Errors
+/// Returns an error if:
+/// - The stream ends in the middle of a frame.
+/// - The frame is longer than `max_length`.
+/// - Reading from the stream fails.
+pub fn read_frame(reader: &mut impl Read, max_length: u32) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; size_of::<u32>()];
+
+    match reader.read(&mut length[..1]) {
+        Ok(0) => return Ok(None),
+        Ok(_) => reader.read_exact(&mut length[1..])?,
+        Err(e) if e.kind() == ErrorKind::Interrupted => return read_frame(reader, max_length),
+        Err(e) => return Err(Error::Io(e)),
+    }
+
+    let length = u32::from_le_bytes(length);
+    if length > max_length {
+        return Err(Error::FrameTooLarge(length as usize));
+    }
+
+    let mut message = vec![0; length as usize];
+    reader.read_exact(&mut message)?;
+    Ok(Some(message))
+}
@@ -2,34 +2,346 @@ use bincode::{
     Decode, Encode,
     config::{self, Configuration},
 };
-use std::io::Write;
-use std::os::unix::net::UnixStream;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::Duration;
 
+pub use connection::{Connection, Events};
 pub use error::{Error, Result};
 
+pub mod connection;
 pub mod error;
+pub mod frame;
 
+/// The socket the system daemon listens on.
 pub const SOCKET_PATH: &str = "/run/zingd.sock";
 
-#[derive(Encode, Decode, Debug, Default, Clone)]
+/// The environment variable that points clients and the daemon at another socket.
+pub const SOCKET_ENV: &str = "ZING_SOCKET";
+
+/// The socket to use when none is given explicitly, `ZING_SOCKET` or else `SOCKET_PATH`.
+#[must_use]
+pub fn socket_path() -> PathBuf {
+    std::env::var_os(SOCKET_ENV)
+        .filter(|path| !path.is_empty())
+        .map_or_else(|| PathBuf::from(SOCKET_PATH), PathBuf::from)
+}
+
+/// The bytes every message starts with, followed by the protocol version.
+pub const MAGIC: [u8; 4] = *b"ZING";
+
+/// The protocol version spoken by this build.
+///
+/// The version must be raised whenever the encoding of an existing message changes.
+/// New `Command` and `Response` variants are only ever appended, and the encoding of
+/// `Response::Error` never changes, so a peer can always explain why it refused a message.
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// The oldest protocol version this build still understands.
+///
+/// Version 1 sent messages without the length in front of them, so they cannot be framed.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+
+/// The length of the header in front of every message.
+const HEADER_LENGTH: usize = MAGIC.len() + size_of::<u16>();
+
+/// Notes that are played together, with their own timing.
+///
+/// Since the buzzer plays one tone at a time, the notes take turns during `duration`,
+/// after which the last note is held for `extended_duration`. A chord without notes is
+/// a rest. The `gap` is a silence after the chord, separating it from the next one.
+#[derive(Encode, Decode, Debug, Default, Clone, PartialEq, Eq)]
 pub struct Chord {
+    pub duration: Duration,
     pub extended_duration: Duration,
+    pub gap: Duration,
     pub notes: Vec<u16>,
 }
 
+impl Chord {
+    /// The total time the chord takes to play, including its gap.
+    #[must_use]
+    pub fn length(&self) -> Duration {
+        self.duration + self.extended_duration + self.gap
+    }
+}
+
+/// How important a melody is compared to other melodies.
+///
+/// A melody with a higher priority interrupts a melody with a lower priority,
+/// which resumes once the more important melody is done.
+#[derive(Encode, Decode, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Priority::Low => write!(f, "low"),
+            Priority::Normal => write!(f, "normal"),
+            Priority::High => write!(f, "high"),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> core::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(format!(
+                "unknown priority '{s}', expected low, normal or high"
+            )),
+        }
+    }
+}
+
 #[derive(Encode, Decode, Debug, Default)]
 pub struct PlayData {
-    pub chord_duration: Duration,
     pub chords: Vec<Chord>,
+    pub priority: Priority,
 }
 
 #[derive(Encode, Decode, Debug)]
 pub enum Command {
+    /// Plays a melody right away, replacing the current one.
     Play(PlayData),
+    /// Stops the current melody and clears the queue.
     Stop,
     Pause,
     Resume,
+    Status,
+    /// Adds a melody to the back of the queue.
+    Enqueue(PlayData),
+    /// Stops the current melody and continues with the next queued one.
+    Skip,
+    /// Removes all queued melodies, the current melody keeps playing.
+    ClearQueue,
+    ListQueue,
+    /// Introduces the client, the daemon answers with its `Capabilities`.
+    Hello(Hello),
+    /// Turns the connection into a stream of `Response::Event`s, after a `Response::Ok`.
+    Subscribe,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Play(_) => write!(f, "play"),
+            Command::Stop => write!(f, "stop"),
+            Command::Pause => write!(f, "pause"),
+            Command::Resume => write!(f, "resume"),
+            Command::Status => write!(f, "status"),
+            Command::Enqueue(_) => write!(f, "enqueue"),
+            Command::Skip => write!(f, "skip"),
+            Command::ClearQueue => write!(f, "clear queue"),
+            Command::ListQueue => write!(f, "list queue"),
+            Command::Hello(_) => write!(f, "hello"),
+            Command::Subscribe => write!(f, "subscribe"),
+        }
+    }
+}
+
+/// Describes the client in a `Command::Hello`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    /// The name and version of the client, for example "zing 0.1.0"
+    pub agent: String,
+    pub min_version: u16,
+    pub max_version: u16,
+}
+
+impl Hello {
+    /// Describes a client built against this version of the protocol.
+    #[must_use]
+    pub fn new(agent: impl Into<String>) -> Self {
+        Self {
+            agent: agent.into(),
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+/// Describes the daemon in its answer to a `Command::Hello`.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The name and version of the daemon, for example "syszingd 0.1.0"
+    pub agent: String,
+    pub min_version: u16,
+    pub max_version: u16,
+
+    /// The lowest frequency in Hz the buzzer can play
+    pub min_frequency: u16,
+
+    /// The highest frequency in Hz the buzzer can play
+    pub max_frequency: u16,
+}
+
+/// The playback state of the daemon at the time it answered a request.
+#[derive(Encode, Decode, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    #[default]
+    Idle,
+    Playing,
+    Paused,
+}
+
+impl fmt::Display for PlaybackState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlaybackState::Idle => write!(f, "idle"),
+            PlaybackState::Playing => write!(f, "playing"),
+            PlaybackState::Paused => write!(f, "paused"),
+        }
+    }
+}
+
+/// The identity of the process on the other end of the daemon socket.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Client {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "uid {} (pid {})", self.uid, self.pid)
+    }
+}
+
+/// A snapshot of what the daemon is currently playing.
+///
+/// The position is the index of the chord being played, the elapsed and remaining times
+/// are derived from the chord durations of the melody.
+#[derive(Encode, Decode, Debug, Default, Clone)]
+pub struct Status {
+    pub state: PlaybackState,
+    pub position: usize,
+    pub total: usize,
+    pub elapsed: Duration,
+    pub remaining: Duration,
+    pub priority: Priority,
+    pub client: Option<Client>,
+}
+
+/// A melody that is waiting in the queue of the daemon.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct QueueEntry {
+    pub chords: usize,
+    pub duration: Duration,
+    pub priority: Priority,
+    pub client: Client,
+}
+
+/// Something that happened to the melodies of the daemon, sent to subscribers.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A melody started playing, or continued after a more important one was done.
+    MelodyStarted {
+        client: Client,
+        priority: Priority,
+        chords: usize,
+        duration: Duration,
+    },
+    /// The chord at `position` of the current melody started playing.
+    ChordAdvanced {
+        position: usize,
+        chord: Chord,
+    },
+    Paused,
+    Resumed,
+    /// The current melody played its last chord.
+    Finished,
+    /// The current melody was stopped, skipped or replaced.
+    Stopped,
+    /// The current melody was interrupted by a melody with a higher priority.
+    Preempted {
+        priority: Priority,
+    },
+}
+
+/// The reason the daemon gives for refusing or failing a command.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub enum ErrorReason {
+    /// The command could not be decoded by the daemon.
+    InvalidCommand(String),
+    /// A melody without any chords was sent.
+    NoChordsProvided,
+    /// A melody with a higher priority is playing and the daemon drops less important ones.
+    Busy,
+    /// The buzzer could not be controlled.
+    Backend(String),
+    /// Something went wrong inside the daemon itself.
+    Internal(String),
+    /// The client speaks a protocol version the daemon does not understand.
+    IncompatibleVersion {
+        client: u16,
+        min_version: u16,
+        max_version: u16,
+    },
+    /// The user already has as many connections open as the daemon allows.
+    TooManyConnections,
+    /// The access policy of the daemon does not allow the client to do this.
+    PermissionDenied,
+    /// A chord of the melody is longer than the daemon allows.
+    ChordTooLong(Duration),
+    /// The melody is longer than the daemon allows.
+    MelodyTooLong(Duration),
+    /// It is quiet hours, only melodies with at least this priority may play.
+    QuietHours(Priority),
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorReason::InvalidCommand(e) => write!(f, "Invalid command: {e}"),
+            ErrorReason::NoChordsProvided => write!(f, "The melody does not contain any chords"),
+            ErrorReason::Busy => write!(f, "A melody with a higher priority is playing"),
+            ErrorReason::Backend(e) => write!(f, "Could not control the buzzer: {e}"),
+            ErrorReason::Internal(e) => write!(f, "Internal daemon error: {e}"),
+            ErrorReason::IncompatibleVersion {
+                client,
+                min_version,
+                max_version,
+            } => write!(
+                f,
+                "Incompatible protocol version: the client speaks version {client}, \
+                 but the daemon only understands versions {min_version} to {max_version}"
+            ),
+            ErrorReason::TooManyConnections => write!(f, "Too many open connections"),
+            ErrorReason::PermissionDenied => write!(f, "Permission denied"),
+            ErrorReason::ChordTooLong(max) => {
+                write!(f, "The melody has a chord longer than {max:?}")
+            }
+            ErrorReason::MelodyTooLong(max) => write!(f, "The melody is longer than {max:?}"),
+            ErrorReason::QuietHours(min) => write!(
+                f,
+                "It is quiet hours, only melodies with {min} priority or higher may play"
+            ),
+        }
+    }
+}
+
+/// The answer the daemon sends back for every `Command`.
+#[derive(Encode, Decode, Debug)]
+pub enum Response {
+    Ok,
+    Error(ErrorReason),
+    State(PlaybackState),
+    Status(Status),
+    Queue(Vec<QueueEntry>),
+    Hello(Capabilities),
+    Event(Event),
 }
 
 impl Command {
@@ -39,11 +351,11 @@ impl Command {
     ///
     /// # Errors
     /// Returns an error if:
+    /// - The sequence does not start with the protocol header
+    /// - The sequence was written with an unsupported protocol version
     /// - The sequence cannot be deserialized
     pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        Ok(bincode::decode_from_slice(bytes, Self::get_config())
-            .map_err(Error::Decode)?
-            .0)
+        decode(bytes)
     }
 
     /// Serializes the `Command` into a byte sequence
@@ -54,34 +366,149 @@ impl Command {
     /// Returns an error if:
     /// - The command cannot be serialized.
     pub fn as_bytes(&self) -> Result<Vec<u8>> {
-        bincode::encode_to_vec(self, Self::get_config()).map_err(Error::Encode)
+        encode(self, PROTOCOL_VERSION)
     }
+}
 
-    fn get_config() -> Configuration {
-        config::standard()
+impl Response {
+    /// Deserializes a `Response` from a byte sequence.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The sequence does not start with the protocol header
+    /// - The sequence was written with an unsupported protocol version
+    /// - The sequence cannot be deserialized
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode(bytes)
+    }
+
+    /// Serializes the `Response` into a byte sequence.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The response cannot be serialized.
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        encode(self, PROTOCOL_VERSION)
+    }
+
+    /// Serializes the `Response` for a peer that speaks another protocol version.
+    ///
+    /// Only a `Response::Error` can be understood by a peer with an unsupported version.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The response cannot be serialized.
+    pub fn as_bytes_for(&self, version: u16) -> Result<Vec<u8>> {
+        encode(self, version)
+    }
+
+    /// Serializes the `Response` without a header, for clients that predate the header.
+    ///
+    /// Only a `Response::Error` can be understood by such clients.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - The response cannot be serialized.
+    pub fn as_legacy_bytes(&self) -> Result<Vec<u8>> {
+        bincode::encode_to_vec(self, get_config()).map_err(Error::Encode)
     }
 }
 
+/// Reads the protocol version from the header of a message.
+///
+/// # Errors
+/// Returns an error if:
+/// - The message does not start with the protocol header
+pub fn message_version(bytes: &[u8]) -> Result<u16> {
+    let (magic, version) = bytes
+        .get(..HEADER_LENGTH)
+        .ok_or(Error::MissingHeader)?
+        .split_at(MAGIC.len());
+
+    if magic != MAGIC {
+        return Err(Error::MissingHeader);
+    }
+
+    Ok(u16::from_le_bytes([version[0], version[1]]))
+}
+
+/// Whether this build understands messages of the given protocol version.
+#[must_use]
+pub fn is_supported(version: u16) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+fn decode<T: Decode<()>>(bytes: &[u8]) -> Result<T> {
+    let version = message_version(bytes)?;
+    let payload = &bytes[HEADER_LENGTH..];
+
+    if !is_supported(version) {
+        return Err(Error::IncompatibleVersion(version));
+    }
+
+    Ok(bincode::decode_from_slice(payload, get_config())
+        .map_err(Error::Decode)?
+        .0)
+}
+
+fn encode<T: Encode>(value: &T, version: u16) -> Result<Vec<u8>> {
+    let mut bytes = Vec::from(MAGIC);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bincode::encode_into_std_write(value, &mut bytes, get_config()).map_err(Error::Encode)?;
+    Ok(bytes)
+}
+
+fn get_config() -> Configuration {
+    config::standard()
+}
+
 /// Sends a serialized command to the Unix socket daemon.
 ///
-/// This function connects to the Unix socket specified by `SOCKET_PATH`
-/// and writes the given command as a byte stream.
+/// This function connects to the Unix socket returned by `socket_path`
+/// and writes the given command as a frame, without waiting for a response.
 ///
 /// # Errors
 /// Returns an error if:
-/// - The Unix socket at `SOCKET_PATH` does not exist or cannot be connected to.
+/// - The Unix socket does not exist or cannot be connected to.
 /// - The command serialization (`command.as_bytes()`) fails.
 /// - Writing to the socket fails.
 ///
 /// # Side effects
 /// - Performs I/O over a Unix socket.
 pub fn send(command: &Command) -> Result<()> {
-    match UnixStream::connect(SOCKET_PATH) {
-        Ok(mut stream) => {
-            let message = command.as_bytes()?;
-            let _ = stream.write_all(&message);
-            Ok(())
-        }
-        Err(e) => Err(Error::Io(e)),
-    }
+    Connection::connect()?.send(command)
+}
+
+/// Sends a serialized command to the daemon listening on `path`, like `send`.
+///
+/// # Errors
+/// Returns an error if the socket cannot be connected to, or the command cannot be
+/// serialized or written.
+pub fn send_to(path: impl AsRef<Path>, command: &Command) -> Result<()> {
+    Connection::connect_to(path)?.send(command)
+}
+
+/// Sends a command to the Unix socket daemon and waits for its `Response`.
+///
+/// Every call opens a new connection, use a `Connection` to send many commands.
+///
+/// # Errors
+/// Returns an error if:
+/// - The Unix socket returned by `socket_path` does not exist or cannot be connected to.
+/// - The command cannot be serialized or the response cannot be deserialized.
+/// - Reading from or writing to the socket fails.
+///
+/// # Side effects
+/// - Performs I/O over a Unix socket.
+pub fn request(command: &Command) -> Result<Response> {
+    Connection::connect()?.request(command)
+}
+
+/// Sends a command to the daemon listening on `path` and waits for its `Response`, like `request`.
+///
+/// # Errors
+/// Returns an error if the socket cannot be connected to, the command cannot be serialized,
+/// the response cannot be deserialized, or reading from or writing to the socket fails.
+pub fn request_to(path: impl AsRef<Path>, command: &Command) -> Result<Response> {
+    Connection::connect_to(path)?.request(command)
 }